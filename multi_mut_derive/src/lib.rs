@@ -0,0 +1,88 @@
+//! `#[derive(MultiMutKeys)]` for the `multi_mut` crate. Use it through the `derive` feature of `multi_mut`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, ConstParam, Data, DeriveInput, Error, Fields, Ident, Type, TypeParam};
+
+/// Derives `multi_mut::MultiMutKeys` for a struct of keys, and generates a struct with the same field names,
+/// called `<Name>Mut`, that holds a mutable reference to the value of every key. The fields may either be keys or
+/// references to borrowed forms of keys, e.g. `String` or `&str` for a map with `String` keys.
+#[proc_macro_derive(MultiMutKeys)]
+pub fn derive_multi_mut_keys(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input.ident, "MultiMutKeys can only be derived for structs with named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input.ident, "MultiMutKeys can only be derived for structs")),
+    };
+    if fields.is_empty() {
+        return Err(Error::new_spanned(&input.ident, "MultiMutKeys needs at least one key"));
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let values_name = Ident::new(&format!("{}Mut", name), Span::call_site());
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let lifetime_params = input.generics.lifetimes();
+    // Defaults are only allowed on the struct, not on the impl, so they are left out.
+    let type_params: Vec<TypeParam> = input.generics.type_params().map(|p| TypeParam { eq_token: None, default: None, ..p.clone() }).collect();
+    let const_params: Vec<ConstParam> = input.generics.const_params().map(|p| ConstParam { eq_token: None, default: None, ..p.clone() }).collect();
+    let predicates = where_clause.map(|w| w.predicates.iter().collect()).unwrap_or_else(Vec::new);
+
+    let names: Vec<&Ident> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let field_vis = fields.iter().map(|f| &f.vis);
+    let doc = format!("Mutable references to the values of the keys of a `{}`.", name);
+
+    // A reference field is looked up with the type it refers to, so that e.g. `&str` fields work with `String` keys.
+    let mut borrowed = Vec::new();
+    let mut lookups = Vec::new();
+    for (field, name) in fields.iter().zip(&names) {
+        match field.ty {
+            Type::Reference(ref reference) => {
+                borrowed.push((*reference.elem).clone());
+                lookups.push(quote!(self.#name));
+            }
+            ref ty => {
+                borrowed.push(ty.clone());
+                lookups.push(quote!(&self.#name));
+            }
+        }
+    }
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #values_name<'a, V: 'a> {
+            #(#field_vis #names: &'a mut V,)*
+        }
+
+        // The parameters are prefixed so that they can't clash with the ones of the key struct.
+        impl<'__a, #(#lifetime_params,)* __M: ?Sized, __V: '__a, #(#type_params,)* #(#const_params),*>
+            ::multi_mut::MultiMutKeys<'__a, __M> for #name #ty_generics
+            where #(__M: ::multi_mut::LookupMut<#borrowed, Value = __V>,)* #(#predicates,)*
+        {
+            type Values = #values_name<'__a, __V>;
+
+            fn get_named_mut_from(&self, map: &'__a mut __M) -> ::std::result::Result<Self::Values, ::multi_mut::MultiMutError> {
+                let [#(#names),*] = ::multi_mut::__macro::Lookup::new(map)
+                    #(.key(#lookups))*
+                    .finish()?;
+                ::std::result::Result::Ok(#values_name { #(#names),* })
+            }
+        }
+    })
+}
@@ -1,444 +1,4567 @@
+// The crate keeps `?Sized` with the generic parameters and the trait bounds in the where-clauses.
+#![allow(clippy::multiple_bound_locations)]
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "derive")]
+extern crate multi_mut_derive;
+#[cfg(all(test, feature = "derive"))]
+extern crate self as multi_mut;   // The derived impls refer to ::multi_mut.
+
+#[cfg(feature = "derive")]
+pub use multi_mut_derive::MultiMutKeys;
+
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::cmp::Eq;
 use std::collections::HashMap;
 use std::collections::BTreeMap;
-use std::mem::transmute;
-use std::slice::Iter;
 
+use std::marker::PhantomData;
+use std::iter::{Cloned, Enumerate, FusedIterator};
+use std::cmp::{min, max};
+use std::mem;
+use std::slice;
+use std::ops::Bound;
+use std::fmt;
+use std::error::Error;
+#[cfg(feature = "audit")]
+use std::panic::Location;
+
+#[cfg(feature = "rayon")]
+use rayon::iter::IntoParallelIterator;
+
+/// The reason why a mutable reference couldn't be handed out. The `index` is the position of the offending key in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiMutError {
+    /// There is no such key in the map.
+    NoSuchKey { index: usize },
+    /// The key points to a value that already has a mutable reference to it.
+    Aliased { index: usize },
+    /// There's no space left in the buffer to keep track of a new reference.
+    BufferDepleted { index: usize },
+    /// The permutation passed to `permute_values` isn't one. Here, the `index` is the position in the permutation
+    /// of the first entry that is out of range or repeated, or its length if it's too short.
+    InvalidPermutation { index: usize },
+}
 
-/// Endows HashMap with extension methods that help getting multiple mutable references to the values contained in it.
-/// Runtime-checking is done to ensure that this is safe: the returned mutable references are guaranteed not to alias.
-pub trait HashMapMultiMut {
-    type Value;
-    type Key: Hash + Eq;
+impl MultiMutError {
+    /// The position of the key that caused the error.
+    pub fn index(&self) -> usize {
+        match *self {
+            MultiMutError::NoSuchKey { index } | MultiMutError::Aliased { index } | MultiMutError::BufferDepleted { index } |
+            MultiMutError::InvalidPermutation { index } => index,
+        }
+    }
+}
 
-    fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut Self::Value, &mut Self::Value)>
-        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+impl fmt::Display for MultiMutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MultiMutError::NoSuchKey { index } => write!(f, "No such key! (key at index {})", index),
+            MultiMutError::Aliased { index } => write!(f, "No aliased references allowed! The key at index {} has been already used.", index),
+            MultiMutError::BufferDepleted { index } => write!(f, "Buffer space is depleted! (key at index {})", index),
+            MultiMutError::InvalidPermutation { index } => write!(f, "Not a permutation! Every index of the keys must appear exactly once. (entry at index {})", index),
+        }
+    }
+}
 
-    fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut Self::Value, &mut Self::Value)
-        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+impl Error for MultiMutError {}
 
-    fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut Self::Value, &mut Self::Value, &mut Self::Value)>
-        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+/// Two keys pointed to the same value: the key at `index` and the earlier key at `first`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AliasError {
+    pub first: usize,
+    pub index: usize,
+}
 
-    fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut Self::Value, &mut Self::Value, &mut Self::Value)
-        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No aliased references allowed! The keys at indices {} and {} point to the same value.", self.first, self.index)
+    }
+}
 
-    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut Self::Value]) -> HashMapMutWrapper<Self::Key, Self::Value>;
+impl Error for AliasError {}
 
-    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, k: &'a [&'a Q], buffer: &'a mut [*mut Self::Value]) -> HashMapMultiMutIter<Q, Self::Key, Self::Value>
-        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+impl From<AliasError> for MultiMutError {
+    fn from(e: AliasError) -> MultiMutError {
+        MultiMutError::Aliased { index: e.index }
+    }
 }
 
-impl<K: Hash + Eq, V> HashMapMultiMut for HashMap<K, V> {
-    type Value = V;
-    type Key = K;
+/// The error returned by `transaction` and `transaction_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError<E> {
+    /// The keys couldn't be turned into mutable references, so the transaction wasn't started.
+    Keys(MultiMutError),
+    /// The transaction returned an error, and the values were restored.
+    Aborted(E),
+}
 
-    fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut V, &mut V)>
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
-        let v_1 = self.get(k_1);
-        let v_2 = self.get(k_2);
+impl<E: fmt::Display> fmt::Display for TransactionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransactionError::Keys(ref e) => write!(f, "The transaction couldn't be started: {}", e),
+            TransactionError::Aborted(ref e) => write!(f, "The transaction was rolled back: {}", e),
+        }
+    }
+}
 
-        match (v_1, v_2) {
-            (Some(v_1), Some(v_2)) => {
+impl<E: Error> Error for TransactionError<E> {}
+
+/// What `multi_mut_batch` does when several keys point to the same value. A missing key is an error
+/// with every policy but `Panic`.
+///
+/// The wrapper returned by `multi_mut` and the iterators built on it don't take a policy, because they hand out
+/// each reference right after looking up its key. A repeated key is only recognised by looking it up, and that
+/// lookup invalidates the reference handed out for the same value earlier, so after that the wrapper can only panic.
+/// `multi_mut_batch` gets around this by making the references only once all the keys are looked up.
+/// `try_iter_multi_mut` compares each key with the earlier ones instead of looking it up, which gives the behavior
+/// of `Error`, and of `Skip` when the `Aliased` errors are filtered out. `Coalesce` can't be had one reference at a time:
+/// the repeated key would need a second reference to a value that is already borrowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Panic, like `iter_multi_mut` does. Missing keys panic as well, instead of returning `MultiMutError::NoSuchKey`.
+    Panic,
+    /// Return `MultiMutError::Aliased` with the index of the repeated key.
+    Error,
+    /// Leave the repeated keys out.
+    Skip,
+    /// Leave the repeated keys out, but record in `MultiMutBatch::slots` which value each key maps to.
+    Coalesce,
+}
 
-                let ptr_1 = v_1 as *const V as *mut V;
-                let ptr_2 = v_2 as *const V as *mut V;
+/// The mutable references returned by `multi_mut_batch`.
+#[derive(Debug)]
+pub struct MultiMutBatch<'a, V: 'a> {
+    /// Each requested value once, in the order their keys first appeared in.
+    pub values: Vec<&'a mut V>,
+    /// With `DuplicatePolicy::Coalesce`, the index in `values` for every input key. Empty otherwise.
+    pub slots: Vec<usize>,
+}
 
-                if ptr_1 == ptr_2 {
-                    None
-                } else {
-                    unsafe { Some((transmute(ptr_1), transmute(ptr_2))) }   // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
-                                                                            // and this function consumed a &mut self, which locks the HashMap so that
-                                                                            // no further aliasing references will be created during the lifetime of these
-                                                                            // references.
-                }
+/// Builds a batch from value pointers, one per input key, applying `policy` to the repeated ones.
+/// The caller must make sure the pointers are valid and nothing else accesses the values for `'a`.
+///
+/// The pointers are taken with one lookup per key, and the references are only made once all the lookups are done.
+/// A lookup invalidates an earlier raw pointer to the value it finds, but not the raw pointers to the other values,
+/// so only the latest pointer to a repeated value is kept. References made before the last lookup wouldn't survive
+/// it in a `BTreeMap`: see `BTreeMapMutWrapper`.
+#[track_caller]
+unsafe fn collect_batch<'a, V, I>(ptrs: I, policy: DuplicatePolicy) -> Result<MultiMutBatch<'a, V>, MultiMutError>
+    where I: Iterator<Item = Option<*mut V>>
+{
+    let mut unique: Vec<*mut V> = Vec::new();
+    let mut slots = Vec::new();
+
+    for (index, ptr) in ptrs.enumerate() {
+        let ptr = match ptr {
+            Some(ptr) => ptr,
+            None if policy == DuplicatePolicy::Panic => panic!("{}", MultiMutError::NoSuchKey { index }),
+            None => return Err(MultiMutError::NoSuchKey { index }),
+        };
+        let slot = match unique.iter().position(|&old_ptr| old_ptr == ptr) {
+            Some(slot) => match policy {
+                DuplicatePolicy::Panic => panic!("{}", AliasError { first: slot, index }),  // Nothing gets skipped before panicking,
+                                                                                              // so the slot is the position of the first key.
+                DuplicatePolicy::Error => return Err(MultiMutError::Aliased { index }),
+                DuplicatePolicy::Skip | DuplicatePolicy::Coalesce => {
+                    unique[slot] = ptr;     // The lookup of the repeated key invalidated the earlier pointer.
+                    slot
+                },
             },
-            _ => None,
+            None => {
+                unique.push(ptr);
+                unique.len() - 1
+            },
+        };
+        if policy == DuplicatePolicy::Coalesce {
+            slots.push(slot);
         }
     }
 
-    fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut V, &mut V)
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
-        let ptr_1 = &self[k_1] as *const V as *mut V;
-        let ptr_2 = &self[k_2] as *const V as *mut V;
+    let values = unique.into_iter().map(|ptr| &mut *ptr).collect();    // The pointers in `unique` don't alias each other.
+    Ok(MultiMutBatch { values, slots })
+}
 
-        if ptr_1 == ptr_2 {
-            panic!("The keys pointed to the same value! Only non-overlapping values can be handled.")
-        } else {
-            unsafe { (transmute(ptr_1), transmute(ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
-                                                            // and this function consumed a &mut self, which locks the HashMap so that
-                                                            // no further aliasing references will be created during the lifetime of these
-                                                            // references.
+/// Finds the first value pointer that is missing or aliases an earlier one, and returns the position of its key
+/// along with a message naming the positions of the offending keys.
+fn find_overlap<V>(ptrs: &[Option<*mut V>]) -> Option<(usize, String)> {
+    for (index, ptr) in ptrs.iter().enumerate() {
+        match *ptr {
+            None => return Some((index, MultiMutError::NoSuchKey { index }.to_string())),
+            Some(_) => if let Some(first) = ptrs[..index].iter().position(|old_ptr| old_ptr == ptr) {
+                return Some((index, AliasError { first, index }.to_string()));
+            },
         }
     }
+    None
+}
 
-    fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut V, &mut V, &mut V)>
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
+/// Panics, naming the positions of the offending keys, if any of the value pointers is missing or aliases an earlier one.
+#[track_caller]
+fn expect_disjoint<V>(ptrs: &[Option<*mut V>]) {
+    if let Some((_, message)) = find_overlap(ptrs) {
+        panic!("{}", message);
+    }
+}
+
+/// Like `expect_disjoint`, but the panic message also includes the offending key.
+#[track_caller]
+fn expect_disjoint_debug<V, Q: ?Sized + fmt::Debug>(ptrs: &[Option<*mut V>], keys: &[&Q]) {
+    if let Some((index, message)) = find_overlap(ptrs) {
+        panic!("{} The key was {:?}.", message, keys[index]);
+    }
+}
+
+/// Checks that none of the value pointers is missing and that no two of them alias.
+fn check_disjoint<V>(ptrs: &[Option<*mut V>]) -> Result<(), MultiMutError> {
+    for (index, ptr) in ptrs.iter().enumerate() {
+        match *ptr {
+            None => return Err(MultiMutError::NoSuchKey { index }),
+            Some(_) if ptrs[..index].contains(ptr) => return Err(MultiMutError::Aliased { index }),
+            Some(_) => (),
+        }
+    }
+    Ok(())
+}
 
-        let v_1 = self.get(k_1);
-        let v_2 = self.get(k_2);
-        let v_3 = self.get(k_3);
+/// Turns an array of value pointers into an array of mutable references, checking them with `check_disjoint` first.
+/// The same caller contract as with `collect_batch` applies.
+unsafe fn collect_disjoint<'a, V, const N: usize>(ptrs: [Option<*mut V>; N]) -> Result<[&'a mut V; N], MultiMutError> {
+    check_disjoint(&ptrs)?;
+    Ok(ptrs.map(|ptr| &mut *ptr.unwrap()))
+}
 
-        match (v_1, v_2, v_3) {
-            (Some(v_1), Some(v_2), Some(v_3)) => {
+/// A map that can be looked into with a borrowed key. This lets the methods that follow paths,
+/// like `get_paths_mut`, descend through nested maps.
+///
+/// # Safety
+///
+/// The crate keeps the references returned by `lookup_mut` as raw pointers while it looks up the other keys,
+/// and tells the values apart by comparing the pointers. So an implementation must make sure that, as long as
+/// the map isn't otherwise accessed:
+///
+/// * looking up the same key again returns a reference to the same place,
+/// * the places found for two keys are either the same place, or places that don't overlap at all, and
+/// * a lookup doesn't move, write to or reborrow the values found by the lookups of the other keys.
+///
+/// This holds for keys of different borrowed forms too, since `get_tuple_mut` mixes them.
+pub unsafe trait LookupMut<Q: ?Sized> {
+    type Value;
 
-                let ptr_1 = v_1 as *const V as *mut V;
-                let ptr_2 = v_2 as *const V as *mut V;
-                let ptr_3 = v_3 as *const V as *mut V;
+    fn lookup_mut(&mut self, k: &Q) -> Option<&mut Self::Value>;
+}
 
-                if ptr_1 == ptr_2 || ptr_2 == ptr_3 || ptr_1 == ptr_3 {
-                    None
-                } else {
-                    unsafe { Some((transmute(ptr_1), transmute(ptr_2), transmute(ptr_3))) } 
-                        // This is safe to do because we checked that ptr_1, ptr_2 and ptr_3 don't alias,
-                        // and this function consumed a &mut self, which locks the HashMap so that
-                        // no further aliasing references will be created during the lifetime of these
-                        // references.
-                }
-            },
-            _ => None,
-        }
+// A HashMap stores every value in its own bucket, and a lookup only reborrows the bucket it finds.
+unsafe impl<K, V, Q: ?Sized> LookupMut<Q> for HashMap<K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq
+{
+    type Value = V;
+
+    fn lookup_mut(&mut self, k: &Q) -> Option<&mut V> {
+        self.get_mut(k)
     }
+}
 
-    fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut V, &mut V, &mut V)
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
-        let ptr_1 = &self[k_1] as *const V as *mut V;
-        let ptr_2 = &self[k_2] as *const V as *mut V;
-        let ptr_3 = &self[k_3] as *const V as *mut V;
+// A BTreeMap stores every value in its own slot of a node, and range_mut only reborrows the slot it finds.
+unsafe impl<K, V, Q: ?Sized> LookupMut<Q> for BTreeMap<K, V>
+        where K: Borrow<Q> + Ord, Q: Ord
+{
+    type Value = V;
 
-        if ptr_1 == ptr_2 || ptr_2 == ptr_3 || ptr_1 == ptr_3 {
-            panic!("The keys pointed to the same value! Only non-overlapping values can be handled.")
-        } else {
-            unsafe { (transmute(ptr_1), transmute(ptr_2), transmute(ptr_3)) }
-                // This is safe to do because we checked that ptr_1, ptr_2 and ptr_3 don't alias,
-                // and this function consumed a &mut self, which locks the HashMap so that
-                // no further aliasing references will be created during the lifetime of these
-                // references.
-        }
+    fn lookup_mut(&mut self, k: &Q) -> Option<&mut V> {
+        self.range_mut::<Q, _>((Bound::Included(k), Bound::Included(k))).next().map(|(_, v)| v)   // See btree_value_ptr.
     }
+}
 
-    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut V]) -> HashMapMutWrapper<K, V>
-    {
-        HashMapMutWrapper { used: 0, map: self, buffer: buffer }
+/// Moves the values behind the references so that `values[i]` ends up with what `values[perm[i]]` had.
+/// Leaves the values as they are if `perm` isn't a permutation of the indices of `values`.
+fn permute_in_place<V>(values: &mut [&mut V], perm: &[usize]) -> Result<(), MultiMutError> {
+    let mut seen = vec![false; values.len()];
+    for (index, &i) in perm.iter().enumerate() {
+        if i >= seen.len() || mem::replace(&mut seen[i], true) {
+            return Err(MultiMutError::InvalidPermutation { index });
+        }
+    }
+    if perm.len() < values.len() {
+        return Err(MultiMutError::InvalidPermutation { index: perm.len() });
     }
 
-    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut V]) -> HashMapMultiMutIter<Q, K, V>
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
-        HashMapMultiMutIter { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter() }
+    let mut done = vec![false; perm.len()];
+    for start in 0..perm.len() {
+        let mut i = start;
+        while !done[i] {
+            done[i] = true;
+            let j = perm[i];
+            if j == start {
+                break;
+            }
+            let (low, high) = values.split_at_mut(max(i, j));
+            mem::swap(&mut *low[min(i, j)], &mut *high[0]);
+            i = j;
+        }
     }
+    Ok(())
+}
 
+/// Restores the snapshots of the values when dropped, unless the transaction was committed by taking the snapshots out.
+struct Rollback<V, S, R, const N: usize>
+    where R: FnMut(&mut V, S)
+{
+    ptrs: [*mut V; N],
+    snapshots: Option<[S; N]>,
+    restore: R,
 }
 
-pub struct HashMapMutWrapper<'a, K: 'a, V: 'a>
-        where K: Hash + Eq
+impl<V, S, R, const N: usize> Drop for Rollback<V, S, R, N>
+    where R: FnMut(&mut V, S)
 {
-    used: usize,
-    map: &'a mut HashMap<K, V>,
-    buffer: &'a mut [*mut V],
+    fn drop(&mut self) {
+        if let Some(snapshots) = self.snapshots.take() {
+            for (&ptr, snapshot) in self.ptrs.iter().zip(IntoIterator::into_iter(snapshots)) {
+                unsafe { (self.restore)(&mut *ptr, snapshot) }  // The references passed to the transaction are gone by now,
+                                                                // so these are the only references to the values.
+            }
+        }
+    }
 }
 
-impl<'a, K, V> HashMapMutWrapper<'a, K, V>
-        where K: Hash + Eq
+/// Runs `f` on the values, restoring them from the snapshots taken with `save` if `f` returns an error or panics.
+fn run_transaction<V, S, Save, Restore, F, T, E, const N: usize>(values: [&mut V; N], mut save: Save, restore: Restore, f: F) -> Result<T, E>
+    where Save: FnMut(&V) -> S, Restore: FnMut(&mut V, S), F: FnOnce([&mut V; N]) -> Result<T, E>
 {
+    let ptrs = values.map(|v| v as *mut V);
+    let snapshots = ptrs.map(|ptr| save(unsafe { &*ptr }));
+    let mut rollback = Rollback { ptrs, snapshots: Some(snapshots), restore };
 
-    pub fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&'a mut V>
-        where K: Borrow<Q>, Q: Hash + Eq
-    {
-        if self.used == self.buffer.len() {
-            panic!("Buffer space is depleted!");
-        }
-        unsafe {    // We introduce the unsafe block early, because we want to be extra safe.
-                    // We create a reference &V here, but there may already exist an earlier
-                    // reference &mut V. This should be fine, since we never access &V through
-                    // this reference before it has proven to be unique. However, in case the 
-                    // compiler jumps to conclusions based of mere *existence* of &V, we want
-                    // to have it exist only inside an unsafe block, to signal that the type
-                    // system invariants may be temporarily broken.
-            let ptr = if let Some(v) = self.map.get(k) { v as *const V as *mut V } else { return None };
+    let result = f(ptrs.map(|ptr| unsafe { &mut *ptr }));
+    if result.is_ok() {
+        rollback.snapshots = None;
+    }
+    result
+}
 
-            for old_ptr in &self.buffer[0..self.used] {
-                if ptr == *old_ptr {
-                    panic!("No aliased references allowed! This key has been already used.");
-                }
+/// Returned by `get_pair_or_single_mut`: either two distinct values, or the single value both keys point to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairOrSingle<T> {
+    Pair(T, T),
+    Same(T),
+}
+
+/// A tuple of keys for `get_tuple_mut`. Every key can be of a different borrowed form, as long as the map
+/// can be looked into with it through `LookupMut`. Implemented for tuples of up to 12 references to keys.
+pub trait KeyTuple<'a, M: ?Sized> {
+    type Values;
+
+    fn get_mut_from(self, map: &'a mut M) -> Result<Self::Values, MultiMutError>;
+}
+
+macro_rules! impl_key_tuple {
+    (@mut_ref $lifetime:lifetime $value:ident $q:ident) => { &$lifetime mut $value };
+    ($($q:ident $index:tt),+) => {
+        impl<'a, 'k, M, V: 'a, $($q: ?Sized),+> KeyTuple<'a, M> for ($(&'k $q,)+)
+            where $(M: LookupMut<$q, Value = V>),+
+        {
+            type Values = ($(impl_key_tuple!(@mut_ref 'a V $q),)+);
+
+            fn get_mut_from(self, map: &'a mut M) -> Result<Self::Values, MultiMutError> {
+                let ptrs = [$(LookupMut::<$q>::lookup_mut(map, self.$index).map(|v| v as *mut V)),+];
+                check_disjoint(&ptrs)?;
+                unsafe { Ok(($(&mut *ptrs[$index].unwrap(),)+)) }   // This is safe to do because we checked that the pointers don't alias,
+                                                                    // the contract of LookupMut makes sure the later lookups
+                                                                    // left the earlier pointers valid, and the map is borrowed
+                                                                    // mutably for 'a.
             }
-            self.buffer[self.used] = ptr;
-            self.used += 1;
-    
-            Some(transmute(ptr))
         }
+    };
+}
+
+impl_key_tuple!(Q1 0);
+impl_key_tuple!(Q1 0, Q2 1);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6, Q8 7);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6, Q8 7, Q9 8);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6, Q8 7, Q9 8, Q10 9);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6, Q8 7, Q9 8, Q10 9, Q11 10);
+impl_key_tuple!(Q1 0, Q2 1, Q3 2, Q4 3, Q5 4, Q6 5, Q7 6, Q8 7, Q9 8, Q10 9, Q11 10, Q12 11);
+
+/// A struct of keys for `get_named_mut`, which returns a struct of mutable references with the same field names.
+/// With the `derive` feature, this can be derived with `#[derive(MultiMutKeys)]`, which also generates the struct
+/// of references, named `<Name>Mut`. The `index` of a `MultiMutError` is the position of the field in the struct.
+pub trait MultiMutKeys<'a, M: ?Sized> {
+    type Values;
+
+    fn get_named_mut_from(&self, map: &'a mut M) -> Result<Self::Values, MultiMutError>;
+}
+
+/// Returns a tuple of mutable references to the values of any number of keys, e.g.
+/// `let (a, b, c) = multi_mut!(map, "a", "b", "c");`. Works with any map that implements `LookupMut`,
+/// and like with `get_tuple_mut`, the keys can be of different borrowed forms.
+///
+/// If all the keys are literals, repeating one of them is a compile error. The literals are compared as they are
+/// written, so the same key spelled in two ways, like `"a"` and `r"a"` or `1` and `0x1`, is only caught at runtime.
+/// Otherwise the keys are checked at runtime, and like `triple_mut`, the macro panics if a key is missing or if two keys point to the same value.
+/// Unlike the methods, the macro knows the types of the keys, so the panic message includes the offending key
+/// if its type implements `Debug`.
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate multi_mut;
+/// # fn main() {
+/// let mut map = std::collections::HashMap::<String, i32>::new();
+/// let (a, b) = multi_mut!(map, "a", "a");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! multi_mut {
+    ($map:expr, $($key:literal),+ $(,)?) => {{
+        const _: () = $crate::__macro::assert_distinct_literals(&[$(stringify!($key)),+]);
+        $crate::multi_mut!(@lookup $map, $($crate::__macro::Literal(&$key).key()),+)
+    }};
+    ($map:expr, $($key:expr),+ $(,)?) => {
+        $crate::multi_mut!(@lookup $map, $($key),+)
+    };
+    (@lookup $map:expr, $($key:expr),+) => {{
+        #[allow(unused_imports)]
+        use $crate::__macro::{DescribeDebug as _, DescribeOther as _, LiteralValue as _};
+        let values = $crate::__macro::Lookup::new(&mut $map)
+            $(.described_key((&$crate::__macro::KeyProbe($key)).describe()))+
+            .finish_or_panic();
+        $crate::multi_mut!(@tuple values [] $($key),+)
+    }};
+    (@tuple $values:ident [$($value:ident)*] $_key:expr $(, $rest:expr)*) => {
+        $crate::multi_mut!(@tuple $values [$($value)* value] $($rest),*)
+    };
+    (@tuple $values:ident [$($value:ident)*]) => {{
+        let [$($value),*] = $values;   // Every `value` comes from a different expansion, so they are all distinct bindings.
+        ($($value,)*)
+    }};
+}
+
+/// Support for `multi_mut!`. Not a part of the public API.
+#[doc(hidden)]
+pub mod __macro {
+    use std::fmt;
+    use {LookupMut, MultiMutError, FmtWith, collect_disjoint};
+
+    /// Looks up the values of `N` keys one by one, so that every key can be of a different borrowed form.
+    pub struct Lookup<'a, M: ?Sized + 'a, V, const N: usize> {
+        map: &'a mut M,
+        ptrs: [Option<*mut V>; N],
+        len: usize,
+        failed: bool,
+        description: Option<String>,
     }
 
-    pub fn mut_ref<Q: ?Sized>(&mut self, k: &Q) -> &'a mut V
-        where K: Borrow<Q>, Q: Hash + Eq {
-            match self.get_mut(k) {
-                Some(v) => v,
-                None => panic!("No such key!"),
+    impl<'a, M: ?Sized, V, const N: usize> Lookup<'a, M, V, N> {
+        pub fn new(map: &'a mut M) -> Self {
+            Lookup { map, ptrs: [None; N], len: 0, failed: false, description: None }
+        }
+
+        pub fn key<Q: ?Sized>(mut self, key: &Q) -> Self
+            where M: LookupMut<Q, Value = V>
+        {
+            let ptr = LookupMut::<Q>::lookup_mut(&mut *self.map, key).map(|v| v as *mut V);
+            self.ptrs[self.len] = ptr;
+            self.len += 1;
+            self
+        }
+
+        /// Like `key`, but if this is the first key that is missing or aliases an earlier one,
+        /// remembers what it looks like for the panic message of `finish_or_panic`.
+        pub fn described_key<Q: ?Sized>(mut self, key: DescribedKey<Q>) -> Self
+            where M: LookupMut<Q, Value = V>
+        {
+            self = self.key(key.key);
+            let ptr = self.ptrs[self.len - 1];
+            if !self.failed && (ptr.is_none() || self.ptrs[..self.len - 1].contains(&ptr)) {
+                self.failed = true;
+                self.description = key.fmt.map(|fmt| format!("{:?}", FmtWith(key.key, fmt)));
             }
+            self
         }
-}
 
-pub struct HashMapMultiMutIter<'a, Q: ?Sized + 'a, K: 'a, V: 'a>
-        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq
-{
-    mut_wrapper: HashMapMutWrapper<'a, K, V>,
-    keys: Iter<'a, &'a Q>,
-}
+        pub fn finish(self) -> Result<[&'a mut V; N], MultiMutError> {
+            assert_eq!(self.len, N, "Every key must be looked up exactly once.");
+            unsafe { collect_disjoint(self.ptrs) }   // This is safe to do because the map is borrowed mutably for 'a, LookupMut
+                                                     // makes sure the later lookups left the earlier pointers valid,
+                                                     // and collect_disjoint checks that the pointers don't alias.
+        }
 
-impl<'a, Q: ?Sized, K, V> Iterator for HashMapMultiMutIter<'a, Q, K, V>
-        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq
-{
-    type Item = &'a mut V;
+        #[track_caller]
+        pub fn finish_or_panic(mut self) -> [&'a mut V; N] {
+            let description = self.description.take();
+            match (self.finish(), description) {
+                (Ok(values), _) => values,
+                (Err(e), Some(key)) => panic!("{} The key was {}.", e, key),
+                (Err(e), None) => panic!("{}", e),
+            }
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.mut_wrapper.used == self.mut_wrapper.buffer.len() { return None };
-        match self.keys.next() {
-            Some(q) => { Some(self.mut_wrapper.mut_ref(q)) },   // Why use panicking mut_ref? Since we provide the keys up front with iterator,
-                                                                // it's likely that a non-existant key is a bug.
-            None => None,
+    /// A key, and a way to format it if its type implements `Debug`.
+    pub struct DescribedKey<'k, Q: ?Sized + 'k> {
+        key: &'k Q,
+        fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>,
+    }
+
+    /// `Literal(&key).key()` turns a key literal into a reference to a key: a string literal is a reference already,
+    /// so it's passed on as it is, and any other literal is referenced.
+    pub struct Literal<'k, T: 'k>(pub &'k T);
+
+    impl<'k> Literal<'k, &'static str> {
+        pub fn key(&self) -> &'static str {
+            self.0
         }
-        
-    } 
+    }
+
+    impl<'k, const N: usize> Literal<'k, &'static [u8; N]> {
+        pub fn key(&self) -> &'static [u8] {
+            *self.0
+        }
+    }
+
+    pub trait LiteralValue<'k, T> {
+        fn key(&self) -> &'k T;
+    }
+
+    impl<'k, T> LiteralValue<'k, T> for Literal<'k, T> {
+        fn key(&self) -> &'k T {
+            self.0
+        }
+    }
+
+    /// `(&KeyProbe(key)).describe()` picks `DescribeDebug` if the key implements `Debug`, since it applies
+    /// without auto-referencing, and falls back to `DescribeOther` otherwise.
+    pub struct KeyProbe<'k, Q: ?Sized + 'k>(pub &'k Q);
+
+    pub trait DescribeDebug<'k, Q: ?Sized> {
+        fn describe(&self) -> DescribedKey<'k, Q>;
+    }
+
+    impl<'k, Q: ?Sized + fmt::Debug> DescribeDebug<'k, Q> for KeyProbe<'k, Q> {
+        fn describe(&self) -> DescribedKey<'k, Q> {
+            DescribedKey { key: self.0, fmt: Some(<Q as fmt::Debug>::fmt) }
+        }
+    }
+
+    pub trait DescribeOther<'k, Q: ?Sized> {
+        fn describe(&self) -> DescribedKey<'k, Q>;
+    }
+
+    impl<'k, Q: ?Sized> DescribeOther<'k, Q> for &KeyProbe<'k, Q> {
+        fn describe(&self) -> DescribedKey<'k, Q> {
+            DescribedKey { key: self.0, fmt: None }
+        }
+    }
+
+    /// Fails the compilation of a `multi_mut!` call if the same key literal appears twice.
+    pub const fn assert_distinct_literals(literals: &[&str]) {
+        let mut i = 0;
+        while i < literals.len() {
+            let mut j = i + 1;
+            while j < literals.len() {
+                if str_eq(literals[i], literals[j]) {
+                    panic!("multi_mut! was given the same key literal twice! Only non-overlapping values can be handled.");
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+    }
+
+    const fn str_eq(a: &str, b: &str) -> bool {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
 }
 
+/// Turns groups of value pointers into groups of mutable references, panicking if a key is missing
+/// or if any two pointers alias, within a group or across groups. The same caller contract as with `collect_batch` applies.
+#[track_caller]
+unsafe fn collect_groups<'a, V>(groups: Vec<Vec<Option<*mut V>>>) -> Vec<Vec<&'a mut V>> {
+    let sizes: Vec<usize> = groups.iter().map(Vec::len).collect();
+    let batch = match collect_batch(groups.into_iter().flatten(), DuplicatePolicy::Panic) {
+        Ok(batch) => batch,
+        Err(e) => panic!("{}", e),
+    };
+    let mut values = batch.values.into_iter();
+    sizes.into_iter().map(|size| values.by_ref().take(size).collect()).collect()
+}
 
-/* BTreeMap */
 
 /// Endows HashMap with extension methods that help getting multiple mutable references to the values contained in it.
 /// Runtime-checking is done to ensure that this is safe: the returned mutable references are guaranteed not to alias.
-pub trait BTreeMapMultiMut {
+pub trait HashMapMultiMut {
     type Value;
-    type Key: Ord;
+    type Key: Hash + Eq;
 
     fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut Self::Value, &mut Self::Value)>
-        where Self::Key: Borrow<Q>, Q: Ord;
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
 
     fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut Self::Value, &mut Self::Value)
-        where Self::Key: Borrow<Q>, Q: Ord;
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Like `pair_mut`, but the panic message also includes the offending key.
+    fn pair_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Hash + Eq + fmt::Debug;
+
+    /// Like `get_pair_mut`, but if both keys point to the same value, returns that value as `PairOrSingle::Same`
+    /// instead of `None`.
+    fn get_pair_or_single_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<PairOrSingle<&mut Self::Value>>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
 
     fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut Self::Value, &mut Self::Value, &mut Self::Value)>
-        where Self::Key: Borrow<Q>, Q: Ord;
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
 
     fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut Self::Value, &mut Self::Value, &mut Self::Value)
-        where Self::Key: Borrow<Q>, Q: Ord;
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
 
-    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut Self::Value]) -> BTreeMapMutWrapper<Self::Key, Self::Value>;
+    /// Like `triple_mut`, but the panic message also includes the offending key.
+    fn triple_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut Self::Value, &mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Hash + Eq + fmt::Debug;
 
-    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, k: &'a [&'a Q], buffer: &'a mut [*mut Self::Value]) -> BTreeMapMultiMutIter<Q, Self::Key, Self::Value>
-        where Self::Key: Borrow<Q>, Q: Ord;
-}
+    /// Returns a tuple of the values of a tuple of keys, e.g. `map.get_tuple_mut(("a", &b, &*c))`.
+    /// Unlike with the other methods, every key can be of a different borrowed form.
+    fn get_tuple_mut<'a, T>(&'a mut self, keys: T) -> Result<T::Values, MultiMutError>
+        where T: KeyTuple<'a, Self>;
 
+    /// Returns a struct of the values of a struct of keys, e.g. `let TransferMut { src, dst, fee } = map.get_named_mut(&keys)?;`.
+    /// Naming the keys keeps them from getting mixed up, like they easily do in tuples. See `MultiMutKeys`.
+    fn get_named_mut<'a, T>(&'a mut self, keys: &T) -> Result<T::Values, MultiMutError>
+        where T: MultiMutKeys<'a, Self>;
 
-impl<K: Ord, V> BTreeMapMultiMut for BTreeMap<K, V> {
+    /// Returns the values of all `keys`, when their number is only known at runtime. Missing and repeated keys
+    /// are reported as errors. Unlike `multi_mut`, this doesn't need a buffer, but it allocates.
+    #[cfg(feature = "alloc")]
+    fn get_many_mut_vec<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<Vec<&mut Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Returns the values of all `keys`, with `None` for the keys that don't exist. The values that exist are checked not to alias.
+    fn get_many_mut_opt<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[Option<&mut Self::Value>; N], AliasError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Like `get_pair_mut`, but returns the keys stored in the map alongside the values.
+    #[allow(clippy::type_complexity)]
+    fn get_pair_key_value_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<((&Self::Key, &mut Self::Value), (&Self::Key, &mut Self::Value))>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut Self::Value]) -> HashMapMutWrapper<'a, Self::Key, Self::Value>;
+
+    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut Self::Value]) -> HashMapMultiMutIter<'a, Q, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Like `iter_multi_mut`, but takes anything that iterates over keys: an array of `&Q`, a `Vec<String>`, or a lazily
+    /// evaluated iterator. The keys are only borrowed while they are looked up, so their lifetime is independent of the
+    /// returned references. If the key type implements `Borrow` for several types (as `String` does), name the borrowed
+    /// type explicitly: `map.iter_multi_mut_by::<str, _>(keys, &mut buffer)`.
+    fn iter_multi_mut_by<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> HashMapMultiMutIterBy<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Like `iter_multi_mut`, but yields the keys stored in the map alongside the values.
+    fn iter_multi_mut_entries<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> HashMapMultiMutEntries<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Fetches the values of all `keys` at once, handling keys that point to the same value according to `policy`.
+    /// Unlike the other methods, this one allocates, but it doesn't need a buffer.
+    fn multi_mut_batch<'a, Q: ?Sized, I>(&'a mut self, keys: I, policy: DuplicatePolicy) -> Result<MultiMutBatch<'a, Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Fetches the values of all `keys` and hands them out as a parallel iterator. Like `iter_multi_mut`, this panics
+    /// if a key doesn't exist or if several keys point to the same value; the check is done once, before any work is started.
+    #[cfg(feature = "rayon")]
+    fn par_iter_multi_mut<Q: ?Sized, I>(&mut self, keys: I) -> rayon::vec::IntoIter<&mut Self::Value>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>, Self::Value: Send;
+
+    /// Fetches the values for several groups of keys, one group per worker thread. All keys are checked to be distinct
+    /// across all the groups, so each group can be moved to its own thread, for example with `std::thread::scope`.
+    /// Panics if a key doesn't exist or if several keys point to the same value.
+    fn split_for_threads<Q: ?Sized, I, G>(&mut self, keys_per_worker: I) -> Vec<Vec<&mut Self::Value>>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator<Item = G>, G: IntoIterator, G::Item: Borrow<Q>;
+
+    /// Follows each `(outer key, inner key)` path into the nested map stored in this map, and returns the values at the ends
+    /// of the paths. Paths may share the outer key, as long as the values at their ends are distinct.
+    /// The error carries the index of the offending path.
+    #[allow(clippy::type_complexity)]
+    fn get_paths_mut<Q: ?Sized, R: ?Sized, const N: usize>(&mut self, paths: [(&Q, &R); N]) -> Result<[&mut <Self::Value as LookupMut<R>>::Value; N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, Self::Value: LookupMut<R>;
+
+    /// Like `pair_mut`, but inserts the values created by `f` for the keys that don't exist yet.
+    /// Panics if the keys are equal; in that case nothing is inserted.
+    fn pair_entry_or_insert_with<Q: ?Sized, F>(&mut self, k_1: &Q, k_2: &Q, f: F) -> (&mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = Self::Key>, F: FnMut() -> Self::Value;
+
+    /// Returns the values of all `keys`, first inserting the values created by `f` for the keys that don't exist yet.
+    /// If the keys aren't distinct, returns an error without inserting anything.
+    fn get_many_or_insert_with<Q: ?Sized, F, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<[&mut Self::Value; N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = Self::Key>, F: FnMut() -> Self::Value;
+
+    /// Swaps the values of two keys.
+    fn swap_values<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Moves the value of each key to the next key, and the value of the last key to the first one.
+    fn rotate_values<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Moves the values around so that `keys[i]` ends up with the value `keys[perm[i]]` had.
+    /// Returns `MultiMutError::InvalidPermutation` if `perm` isn't a permutation of `0..keys.len()`.
+    fn permute_values<Q: ?Sized>(&mut self, keys: &[&Q], perm: &[usize]) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Removes the value of `src` from the map and passes it to `f` along with the value of `dst`, returning what `f` returns.
+    /// If either key is missing or both point to the same value, returns an error without changing the map;
+    /// `src` has the index 0 and `dst` the index 1.
+    fn merge_into<Q: ?Sized, F, R>(&mut self, src: &Q, dst: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut Self::Value, Self::Value) -> R;
+
+    /// Removes all `keys` from the map and returns the stored keys with their values. Every key is checked to exist
+    /// and to be distinct before anything is removed, so on error the map is left untouched.
+    #[allow(clippy::type_complexity)]
+    fn remove_many<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[(Self::Key, Self::Value); N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq;
+
+    /// Runs `f` on the values of all `keys` as a transaction: if `f` returns an error or panics, the values are restored
+    /// to what they were before. The values are snapshotted by cloning them.
+    fn transaction<Q: ?Sized, F, T, E, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<T, TransactionError<E>>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, Self::Value: Clone, F: FnOnce([&mut Self::Value; N]) -> Result<T, E>;
+
+    /// Like `transaction`, but the values are snapshotted with `save` and restored with `restore`,
+    /// for values that can't be cloned or that have a cheaper way to undo the changes.
+    fn transaction_with<Q: ?Sized, S, Save, Restore, F, T, E, const N: usize>(&mut self, keys: [&Q; N], save: Save, restore: Restore, f: F) -> Result<T, TransactionError<E>>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, Save: FnMut(&Self::Value) -> S, Restore: FnMut(&mut Self::Value, S),
+              F: FnOnce([&mut Self::Value; N]) -> Result<T, E>;
+
+    /// Passes the values of the two keys to `f` and returns what it returns. The references can't escape the closure.
+    fn with_pair_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut Self::Value, &mut Self::Value) -> R;
+
+    /// Passes the values of the three keys to `f` and returns what it returns. The references can't escape the closure.
+    fn with_triple_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut Self::Value, &mut Self::Value, &mut Self::Value) -> R;
+
+    /// Passes the values of all `keys` to `f` and returns what it returns. The references can't escape the closure.
+    fn with_many_mut<Q: ?Sized, F, R, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, F: FnOnce([&mut Self::Value; N]) -> R;
+
+    /// Starting from `start`, follows the keys returned by `next` from value to value until it returns `None`,
+    /// and returns the values along the chain. `next` returns the keys owned, so that none of them is borrowed
+    /// from a value in the map while the map is looked into. A chain that loops back to an already visited value is reported as
+    /// `MultiMutError::Aliased`, and a link to a missing key as `MultiMutError::NoSuchKey`; the index is the position in the chain.
+    fn follow_chain_mut<Q: ?Sized, F>(&mut self, start: &Q, next: F) -> Result<Vec<&mut Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, F: FnMut(&Self::Value) -> Option<Self::Key>;
+
+    /// Returns the value of `center` along with the values of its neighbors. Missing neighbors are returned as `None`.
+    /// Returns `None` if `center` is missing, or if any two of the keys point to the same value.
+    #[allow(clippy::type_complexity)]
+    fn get_with_neighbors_mut<Q: ?Sized, I>(&mut self, center: &Q, neighbor_keys: I) -> Option<(&mut Self::Value, Vec<Option<&mut Self::Value>>)>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Like `iter_multi_mut`, but doesn't panic: missing keys, repeated keys and keys that don't fit in the buffer
+    /// are reported as errors carrying the index of the key, and the iteration continues with the next key.
+    /// The repeated keys are told apart by comparing them with the earlier keys, so they aren't looked up again.
+    fn try_iter_multi_mut<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> HashMapMultiMutTryIter<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>;
+}
+
+impl<K: Hash + Eq, V> HashMapMultiMut for HashMap<K, V> {
     type Value = V;
     type Key = K;
 
     fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut V, &mut V)>
-        where K: Borrow<Q>, Q: Ord
+        where K: Borrow<Q>, Q: Hash + Eq
     {
-        let v_1 = self.get(k_1);
-        let v_2 = self.get(k_2);
-
-        match (v_1, v_2) {
-            (Some(v_1), Some(v_2)) => {
+        let ptr_1 = self.get_mut(k_1).map(|v| v as *mut V);
+        let ptr_2 = self.get_mut(k_2).map(|v| v as *mut V);
 
-                let ptr_1 = v_1 as *const V as *mut V;
-                let ptr_2 = v_2 as *const V as *mut V;
+        match (ptr_1, ptr_2) {
+            (Some(ptr_1), Some(ptr_2)) => {
 
                 if ptr_1 == ptr_2 {
                     None
                 } else {
-                    unsafe { Some((transmute(ptr_1), transmute(ptr_2))) }   // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
-                                                                            // and this function consumed a &mut self, which locks the HashMap so that
-                                                                            // no further aliasing references will be created during the lifetime of these
-                                                                            // references.
+                    unsafe { Some((&mut *ptr_1, &mut *ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
+                                                                // and this function consumed a &mut self, which locks the HashMap so that
+                                                                // no further aliasing references will be created during the lifetime of these
+                                                                // references. Looking up k_2 doesn't invalidate ptr_1, see collect_batch.
                 }
             },
             _ => None,
         }
     }
 
+    #[track_caller]
     fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut V, &mut V)
-        where K: Borrow<Q>, Q: Ord
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let ptrs = [k_1, k_2].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        expect_disjoint(&ptrs);
+        let [ptr_1, ptr_2] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2) } // This is safe to do because expect_disjoint checked that ptr_1 and ptr_2 don't alias,
+                                              // and this function consumed a &mut self, which locks the HashMap so that
+                                              // no further aliasing references will be created during the lifetime of these
+                                              // references.
+    }
+
+    #[track_caller]
+    fn pair_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut V, &mut V)
+        where K: Borrow<Q>, Q: Hash + Eq + fmt::Debug
+    {
+        let ptrs = [k_1, k_2].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        expect_disjoint_debug(&ptrs, &[k_1, k_2]);
+        let [ptr_1, ptr_2] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2) } // This is safe to do for the same reasons as in pair_mut.
+    }
+
+    fn get_pair_or_single_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<PairOrSingle<&mut V>>
+        where K: Borrow<Q>, Q: Hash + Eq
     {
-        let ptr_1 = &self[k_1] as *const V as *mut V;
-        let ptr_2 = &self[k_2] as *const V as *mut V;
+        let ptr_1 = self.get_mut(k_1).map(|v| v as *mut V);
+        let ptr_2 = self.get_mut(k_2).map(|v| v as *mut V);
 
-        if ptr_1 == ptr_2 {
-            panic!("The keys pointed to the same value! Only non-overlapping values can be handled.")
-        } else {
-            unsafe { (transmute(ptr_1), transmute(ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
-                                                            // and this function consumed a &mut self, which locks the HashMap so that
-                                                            // no further aliasing references will be created during the lifetime of these
-                                                            // references.
+        match (ptr_1, ptr_2) {
+            (Some(ptr_1), Some(ptr_2)) => {
+
+                if ptr_1 == ptr_2 {
+                    unsafe { Some(PairOrSingle::Same(&mut *ptr_2)) }   // This is safe to do because only one reference is made
+                                                                        // out of the two equal pointers. Looking up k_2 invalidated ptr_1.
+                } else {
+                    unsafe { Some(PairOrSingle::Pair(&mut *ptr_1, &mut *ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias.
+                }
+            },
+            _ => None,
         }
     }
 
     fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut V, &mut V, &mut V)>
-        where K: Borrow<Q>, Q: Ord
+        where K: Borrow<Q>, Q: Hash + Eq
     {
 
-        let v_1 = self.get(k_1);
-        let v_2 = self.get(k_2);
-        let v_3 = self.get(k_3);
+        let ptr_1 = self.get_mut(k_1).map(|v| v as *mut V);
+        let ptr_2 = self.get_mut(k_2).map(|v| v as *mut V);
+        let ptr_3 = self.get_mut(k_3).map(|v| v as *mut V);
 
-        match (v_1, v_2, v_3) {
-            (Some(v_1), Some(v_2), Some(v_3)) => {
-
-                let ptr_1 = v_1 as *const V as *mut V;
-                let ptr_2 = v_2 as *const V as *mut V;
-                let ptr_3 = v_3 as *const V as *mut V;
+        match (ptr_1, ptr_2, ptr_3) {
+            (Some(ptr_1), Some(ptr_2), Some(ptr_3)) => {
 
                 if ptr_1 == ptr_2 || ptr_2 == ptr_3 || ptr_1 == ptr_3 {
                     None
                 } else {
-                    unsafe { Some((transmute(ptr_1), transmute(ptr_2), transmute(ptr_3))) } 
+                    unsafe { Some((&mut *ptr_1, &mut *ptr_2, &mut *ptr_3)) }
                         // This is safe to do because we checked that ptr_1, ptr_2 and ptr_3 don't alias,
                         // and this function consumed a &mut self, which locks the HashMap so that
                         // no further aliasing references will be created during the lifetime of these
-                        // references.
+                        // references. The later lookups don't invalidate the earlier pointers, see collect_batch.
                 }
             },
             _ => None,
         }
     }
 
+    #[track_caller]
     fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut V, &mut V, &mut V)
-        where K: Borrow<Q>, Q: Ord
+        where K: Borrow<Q>, Q: Hash + Eq
     {
-        let ptr_1 = &self[k_1] as *const V as *mut V;
-        let ptr_2 = &self[k_2] as *const V as *mut V;
-        let ptr_3 = &self[k_3] as *const V as *mut V;
-
-        if ptr_1 == ptr_2 || ptr_2 == ptr_3 || ptr_1 == ptr_3 {
-            panic!("The keys pointed to the same value! Only non-overlapping values can be handled.")
-        } else {
-            unsafe { (transmute(ptr_1), transmute(ptr_2), transmute(ptr_3)) }
-                // This is safe to do because we checked that ptr_1, ptr_2 and ptr_3 don't alias,
-                // and this function consumed a &mut self, which locks the HashMap so that
-                // no further aliasing references will be created during the lifetime of these
-                // references.
-        }
+        let ptrs = [k_1, k_2, k_3].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        expect_disjoint(&ptrs);
+        let [ptr_1, ptr_2, ptr_3] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2, &mut *ptr_3) }
+            // This is safe to do because expect_disjoint checked that ptr_1, ptr_2 and ptr_3 don't alias,
+            // and this function consumed a &mut self, which locks the HashMap so that
+            // no further aliasing references will be created during the lifetime of these
+            // references.
     }
 
-    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut V]) -> BTreeMapMutWrapper<K, V>
+    #[track_caller]
+    fn triple_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut V, &mut V, &mut V)
+        where K: Borrow<Q>, Q: Hash + Eq + fmt::Debug
     {
-        BTreeMapMutWrapper { used: 0, map: self, buffer: buffer }
+        let ptrs = [k_1, k_2, k_3].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        expect_disjoint_debug(&ptrs, &[k_1, k_2, k_3]);
+        let [ptr_1, ptr_2, ptr_3] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2, &mut *ptr_3) } // This is safe to do for the same reasons as in triple_mut.
     }
 
-    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut V]) -> BTreeMapMultiMutIter<Q, K, V>
-        where K: Borrow<Q>, Q: Ord
+    #[cfg(feature = "alloc")]
+    fn get_many_mut_vec<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<Vec<&mut V>, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        self.multi_mut_batch(keys.iter().cloned(), DuplicatePolicy::Error).map(|batch| batch.values)
+    }
+
+    fn get_many_mut_opt<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[Option<&mut V>; N], AliasError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let ptrs = keys.map(|k| self.get_mut(k).map(|v| v as *mut V));
+
+        for (index, ptr) in ptrs.iter().enumerate() {
+            if ptr.is_some() {
+                if let Some(first) = ptrs[..index].iter().position(|old_ptr| old_ptr == ptr) {
+                    return Err(AliasError { first, index });
+                }
+            }
+        }
+        unsafe { Ok(ptrs.map(|ptr| ptr.map(|ptr| &mut *ptr))) }  // This is safe to do because we checked that the pointers of the keys
+                                                                // that exist don't alias, and the missing ones don't become references.
+    }
+
+    fn get_tuple_mut<'a, T>(&'a mut self, keys: T) -> Result<T::Values, MultiMutError>
+        where T: KeyTuple<'a, Self>
+    {
+        keys.get_mut_from(self)
+    }
+
+    fn get_named_mut<'a, T>(&'a mut self, keys: &T) -> Result<T::Values, MultiMutError>
+        where T: MultiMutKeys<'a, Self>
+    {
+        keys.get_named_mut_from(self)
+    }
+
+    fn get_pair_key_value_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<((&K, &mut V), (&K, &mut V))>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let entry_1 = hash_key_value_ptrs(self, k_1);
+        let entry_2 = hash_key_value_ptrs(self, k_2);
+
+        match (entry_1, entry_2) {
+            (Some((key_1, ptr_1)), Some((key_2, ptr_2))) => {
+
+                if ptr_1 == ptr_2 {
+                    None
+                } else {
+                    unsafe { Some(((&*key_1, &mut *ptr_1), (&*key_2, &mut *ptr_2))) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias.
+                                                                                  // The keys are only handed out shared, and a key is stored
+                                                                                  // apart from its value, so it doesn't overlap either of them.
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut V]) -> HashMapMutWrapper<'a, K, V>
+    {
+        HashMapMutWrapper { used: 0, map: self, buffer, audit: BorrowAudit::default() }
+    }
+
+    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut V]) -> HashMapMultiMutIter<'a, Q, K, V>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        self.iter_multi_mut_by(keys.iter().cloned(), buffer)
+    }
+
+    fn iter_multi_mut_by<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> HashMapMultiMutIterBy<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        HashMapMultiMutIterBy { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter(), key_fmt: None, key_type: PhantomData }
+    }
+
+    fn iter_multi_mut_entries<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> HashMapMultiMutEntries<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        HashMapMultiMutEntries { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter(), key_fmt: None, key_type: PhantomData }
+    }
+
+    #[track_caller]
+    fn multi_mut_batch<'a, Q: ?Sized, I>(&'a mut self, keys: I, policy: DuplicatePolicy) -> Result<MultiMutBatch<'a, V>, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        let ptrs = keys.into_iter().map(|q| self.get_mut(q.borrow()).map(|v| v as *mut V));
+        unsafe { collect_batch(ptrs, policy) }    // This is safe to do because the pointers come from get_mut, collect_batch makes at most
+                                                    // one reference per value, and the map stays borrowed mutably for 'a.
+    }
+
+    #[cfg(feature = "rayon")]
+    #[track_caller]
+    fn par_iter_multi_mut<Q: ?Sized, I>(&mut self, keys: I) -> rayon::vec::IntoIter<&mut V>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>, V: Send
+    {
+        match self.multi_mut_batch(keys, DuplicatePolicy::Panic) {
+            Ok(batch) => batch.values.into_par_iter(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[track_caller]
+    fn split_for_threads<Q: ?Sized, I, G>(&mut self, keys_per_worker: I) -> Vec<Vec<&mut V>>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator<Item = G>, G: IntoIterator, G::Item: Borrow<Q>
+    {
+        let groups = keys_per_worker.into_iter()
+            .map(|group| group.into_iter().map(|q| self.get_mut(q.borrow()).map(|v| v as *mut V)).collect())
+            .collect();
+        unsafe { collect_groups(groups) } // This is safe to do because collect_groups checks the pointers of all the groups against
+                                            // each other, so no value ends up in two groups, and the map stays borrowed mutably.
+    }
+
+    fn get_paths_mut<Q: ?Sized, R: ?Sized, const N: usize>(&mut self, paths: [(&Q, &R); N]) -> Result<[&mut V::Value; N], MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, V: LookupMut<R>
+    {
+        unsafe {   // See HashMapMutWrapper::try_get_mut for why the unsafe block starts this early: paths sharing an outer key
+                    // create a new &mut to the inner map while pointers to values in it already exist.
+            let ptrs = paths.map(|(q, r)| {
+                let inner = self.get_mut(q)? as *mut V;
+                (*inner).lookup_mut(r).map(|v| v as *mut V::Value)
+            });
+            collect_disjoint(ptrs)  // This is safe to do because collect_disjoint checks that the values at the ends of the paths don't alias.
+                                    // The inner maps are reached through raw pointers, and looking up a second path in the same
+                                    // inner map reborrows that map, not the value found for the first path.
+        }
+    }
+
+    #[track_caller]
+    fn pair_entry_or_insert_with<Q: ?Sized, F>(&mut self, k_1: &Q, k_2: &Q, mut f: F) -> (&mut V, &mut V)
+        where K: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = K>, F: FnMut() -> V
+    {
+        if k_1 == k_2 {
+            panic!("{}", AliasError { first: 0, index: 1 })
+        }
+        // Inserting may move the values around, so all the insertions are done before any pointers are taken.
+        for &k in &[k_1, k_2] {
+            if !self.contains_key(k) {
+                self.insert(k.to_owned(), f());
+            }
+        }
+        self.pair_mut(k_1, k_2)
+    }
+
+    fn get_many_or_insert_with<Q: ?Sized, F, const N: usize>(&mut self, keys: [&Q; N], mut f: F) -> Result<[&mut V; N], MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = K>, F: FnMut() -> V
+    {
+        for (index, k) in keys.iter().enumerate() {
+            if keys[..index].contains(k) {
+                return Err(MultiMutError::Aliased { index });
+            }
+        }
+        // Inserting may move the values around, so all the insertions are done before any pointers are taken.
+        for &k in &keys {
+            if !self.contains_key(k) {
+                self.insert(k.to_owned(), f());
+            }
+        }
+        let ptrs = keys.map(|k| self.get_mut(k).map(|v| v as *mut V));
+        unsafe { collect_disjoint(ptrs) } // This is safe to do because nothing is inserted after the pointers are taken,
+                                            // and collect_disjoint checks that they don't alias.
+    }
+
+    fn swap_values<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let ptrs = [k_1, k_2].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        let [v_1, v_2] = unsafe { collect_disjoint(ptrs)? };  // This is safe to do because collect_disjoint checks that the pointers don't alias.
+        mem::swap(v_1, v_2);
+        Ok(())
+    }
+
+    fn rotate_values<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let perm: Vec<usize> = (0..keys.len()).map(|i| (i + keys.len() - 1) % keys.len()).collect();
+        self.permute_values(keys, &perm)
+    }
+
+    fn permute_values<Q: ?Sized>(&mut self, keys: &[&Q], perm: &[usize]) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let mut values = self.multi_mut_batch(keys.iter().cloned(), DuplicatePolicy::Error)?.values;
+        permute_in_place(&mut values, perm)
+    }
+
+    fn merge_into<Q: ?Sized, F, R>(&mut self, src: &Q, dst: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut V, V) -> R
+    {
+        let ptrs = [src, dst].map(|k| self.get_mut(k).map(|v| v as *mut V));
+        check_disjoint(&ptrs)?;
+
+        let src_value = self.remove(src).expect("The key was checked to exist.");
+        let dst_value = self.get_mut(dst).expect("The key was checked to exist.");
+        Ok(f(dst_value, src_value))
+    }
+
+    fn remove_many<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[(K, V); N], MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let ptrs = keys.map(|k| self.get_mut(k).map(|v| v as *mut V));
+        check_disjoint(&ptrs)?;
+
+        Ok(keys.map(|k| self.remove_entry(k).expect("The key was checked to exist.")))
+    }
+
+    fn transaction<Q: ?Sized, F, T, E, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<T, TransactionError<E>>
+        where K: Borrow<Q>, Q: Hash + Eq, V: Clone, F: FnOnce([&mut V; N]) -> Result<T, E>
+    {
+        self.transaction_with(keys, V::clone, |v, snapshot| *v = snapshot, f)
+    }
+
+    fn transaction_with<Q: ?Sized, S, Save, Restore, F, T, E, const N: usize>(&mut self, keys: [&Q; N], save: Save, restore: Restore, f: F) -> Result<T, TransactionError<E>>
+        where K: Borrow<Q>, Q: Hash + Eq, Save: FnMut(&V) -> S, Restore: FnMut(&mut V, S),
+              F: FnOnce([&mut V; N]) -> Result<T, E>
     {
-        BTreeMapMultiMutIter { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter() }
+        let ptrs = keys.map(|k| self.get_mut(k).map(|v| v as *mut V));
+        let values = unsafe { collect_disjoint(ptrs) }.map_err(TransactionError::Keys)?; // This is safe to do because collect_disjoint checks
+                                                                                            // that the pointers don't alias.
+        run_transaction(values, save, restore, f).map_err(TransactionError::Aborted)
+    }
+
+    fn with_pair_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut V, &mut V) -> R
+    {
+        self.with_many_mut([k_1, k_2], |[v_1, v_2]| f(v_1, v_2))
+    }
+
+    fn with_triple_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, F: FnOnce(&mut V, &mut V, &mut V) -> R
+    {
+        self.with_many_mut([k_1, k_2, k_3], |[v_1, v_2, v_3]| f(v_1, v_2, v_3))
+    }
+
+    fn with_many_mut<Q: ?Sized, F, R, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, F: FnOnce([&mut V; N]) -> R
+    {
+        let ptrs = keys.map(|k| self.get_mut(k).map(|v| v as *mut V));
+        let values = unsafe { collect_disjoint(ptrs)? };  // This is safe to do because collect_disjoint checks that the pointers don't alias,
+                                                            // and the references can't outlive the call to f.
+        Ok(f(values))
+    }
+
+    fn follow_chain_mut<Q: ?Sized, F>(&mut self, start: &Q, mut next: F) -> Result<Vec<&mut V>, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq, F: FnMut(&V) -> Option<K>
+    {
+        let mut chain: Vec<*mut V> = Vec::new();
+        unsafe {   // See HashMapMutWrapper::try_get_mut for why the unsafe block starts this early: the values
+                    // are read through the pointers in between the lookups.
+            let mut ptr = match self.get_mut(start) { Some(v) => v as *mut V, None => return Err(MultiMutError::NoSuchKey { index: 0 }) };
+            loop {
+                let index = chain.len();
+                if chain.contains(&ptr) {
+                    return Err(MultiMutError::Aliased { index });
+                }
+                chain.push(ptr);
+                ptr = match next(&*ptr) {
+                    Some(k) => match self.get_mut::<K>(&k) { Some(v) => v as *mut V, None => return Err(MultiMutError::NoSuchKey { index: index + 1 }) },
+                    None => break,
+                };
+            }
+            Ok(chain.into_iter().map(|ptr| &mut *ptr).collect())  // This is safe to do because a value that was already in the chain
+                                                                    // ends the walk with an error, so every value is in the chain once.
+        }
+    }
+
+    fn get_with_neighbors_mut<Q: ?Sized, I>(&mut self, center: &Q, neighbor_keys: I) -> Option<(&mut V, Vec<Option<&mut V>>)>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        let center = self.get_mut(center)? as *mut V;
+        let mut found = vec![center];
+        let mut neighbors = Vec::new();
+
+        for k in neighbor_keys {
+            let ptr = self.get_mut(k.borrow()).map(|v| v as *mut V);
+            if let Some(ptr) = ptr {
+                if found.contains(&ptr) {
+                    return None;
+                }
+                found.push(ptr);
+            }
+            neighbors.push(ptr);
+        }
+
+        unsafe { Some((&mut *center, neighbors.into_iter().map(|ptr| ptr.map(|ptr| &mut *ptr)).collect())) }
+            // This is safe to do because every neighbor that exists was checked against the center
+            // and the earlier neighbors before it was added to `found`.
+    }
+
+    fn try_iter_multi_mut<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> HashMapMultiMutTryIter<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Hash + Eq, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        HashMapMultiMutTryIter { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter().enumerate(), handed_out: Vec::new(), key_type: PhantomData }
     }
 
 }
 
-pub struct BTreeMapMutWrapper<'a, K: 'a, V: 'a>
-        where K: Ord
+/// Looks up the key stored in the map and a pointer to the value.
+/// `HashMap` has no single lookup that returns `(&K, &mut V)`, like `BTreeMap::range_mut` does, so this takes two. The value
+/// is looked up first: `get_mut` reborrows the whole entry mutably, which would invalidate a key pointer taken before it,
+/// whereas the shared lookup of the key that comes after leaves the value pointer usable.
+fn hash_key_value_ptrs<K, V, Q: ?Sized>(map: &mut HashMap<K, V>, k: &Q) -> Option<(*const K, *mut V)>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq
+{
+    let ptr = map.get_mut(k)? as *mut V;
+    let key = map.get_key_value(k)?.0 as *const K;
+    Some((key, ptr))
+}
+
+/// A key formatted with the `Debug` implementation that was captured for it, for keys whose type is only known to
+/// implement `Debug` where the formatter was picked.
+struct FmtWith<'k, Q: ?Sized + 'k>(&'k Q, fn(&Q, &mut fmt::Formatter) -> fmt::Result);
+
+impl<'k, Q: ?Sized> fmt::Debug for FmtWith<'k, Q> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (self.1)(self.0, f)
+    }
+}
+
+/// Where a reference handed out by a wrapper was borrowed. Recorded with the `audit` feature.
+#[cfg(feature = "audit")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct BorrowSite {
+    pub location: &'static Location<'static>,
+    /// The `Debug` form of the key, if it was borrowed with one of the `_debug` methods or an iterator with `debug_keys`.
+    pub key: Option<String>,
+}
+
+#[cfg(feature = "audit")]
+impl fmt::Debug for BorrowSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key {
+            Some(ref key) => write!(f, "{} at {}", key, self.location),
+            None => write!(f, "{}", self.location),
+        }
+    }
+}
+
+/// With the `audit` feature, records where the references handed out by a wrapper were borrowed, so that
+/// an alias violation can report both sites. Without it, this is empty and does nothing.
+#[derive(Default)]
+struct BorrowAudit {
+    #[cfg(feature = "audit")]
+    sites: Vec<BorrowSite>,
+    #[cfg(feature = "audit")]
+    conflict: Option<usize>,
+}
+
+impl BorrowAudit {
+    /// Records the caller as the site of a new borrow.
+    #[track_caller]
+    fn record(&mut self) {
+        #[cfg(feature = "audit")]
+        self.sites.push(BorrowSite { location: Location::caller(), key: None });
+    }
+
+    /// Records that the earlier borrow at `_slot` was requested again.
+    fn conflict(&mut self, _slot: usize) {
+        #[cfg(feature = "audit")]
+        { self.conflict = Some(_slot); }
+    }
+
+    /// Attaches the `Debug` form of the key to the latest borrow.
+    fn describe_last<Q: ?Sized + fmt::Debug>(&mut self, _key: &Q) {
+        #[cfg(feature = "audit")]
+        { if let Some(site) = self.sites.last_mut() { site.key = Some(format!("{:?}", _key)); } }
+    }
+
+    /// Unwraps the result of a lookup. If there's a way to format the key, it's attached to the new borrow, or
+    /// included in the panic message.
+    #[track_caller]
+    fn settle<T, Q: ?Sized>(&mut self, result: Result<T, MultiMutError>, key: &Q, fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>) -> T {
+        match (result, fmt) {
+            (Ok(v), Some(fmt)) => {
+                self.describe_last(&FmtWith(key, fmt));
+                v
+            },
+            (Ok(v), None) => v,
+            (Err(e), Some(fmt)) => self.fail_with_key(e, &FmtWith(key, fmt)),
+            (Err(e), None) => self.fail(e),
+        }
+    }
+
+    #[track_caller]
+    fn fail(&self, e: MultiMutError) -> ! {
+        self.fail_with(e, String::new())
+    }
+
+    /// Like `fail`, but the panic message also includes the key.
+    #[track_caller]
+    fn fail_with_key<Q: ?Sized + fmt::Debug>(&self, e: MultiMutError, key: &Q) -> ! {
+        self.fail_with(e, format!(" The key was {:?}.", key))
+    }
+
+    #[track_caller]
+    fn fail_with(&self, e: MultiMutError, key: String) -> ! {
+        #[cfg(feature = "audit")]
+        {
+            if let (MultiMutError::Aliased { .. }, Some(slot)) = (e, self.conflict) {
+                panic!("{}{} First borrowed: {:?}, and again at {}.", e, key, self.sites[slot], Location::caller());
+            }
+        }
+        panic!("{}{}", e, key)
+    }
+}
+
+pub struct HashMapMutWrapper<'a, K: 'a, V: 'a>
+        where K: Hash + Eq
 {
     used: usize,
-    map: &'a mut BTreeMap<K, V>,
+    map: &'a mut HashMap<K, V>,
     buffer: &'a mut [*mut V],
+    audit: BorrowAudit,
 }
 
-impl<'a, K, V> BTreeMapMutWrapper<'a, K, V>
-        where K: Ord
+// The buffer of raw pointers makes the wrapper !Send and !Sync by default. The pointers are only ever compared,
+// never dereferenced, so the wrapper is as thread-safe as the `&mut HashMap<K, V>` it holds.
+unsafe impl<'a, K, V> Send for HashMapMutWrapper<'a, K, V>
+        where K: Hash + Eq + Send, V: Send
+{}
+
+unsafe impl<'a, K, V> Sync for HashMapMutWrapper<'a, K, V>
+        where K: Hash + Eq + Sync, V: Sync
+{}
+
+/// Lists the active borrows. Their sites are only known with the `audit` feature.
+impl<'a, K, V> fmt::Debug for HashMapMutWrapper<'a, K, V>
+        where K: Hash + Eq
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("HashMapMutWrapper");
+        s.field("used", &self.used).field("capacity", &self.buffer.len());
+        #[cfg(feature = "audit")]
+        s.field("borrows", &self.audit.sites);
+        s.finish()
+    }
+}
+
+impl<'a, K, V> HashMapMutWrapper<'a, K, V>
+        where K: Hash + Eq
 {
 
+    #[track_caller]
     pub fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&'a mut V>
-        where K: Borrow<Q>, Q: Ord
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let index = self.used;
+        match self.try_get_mut(k, index) {
+            Ok(v) => Some(v),
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail(e),
+        }
+    }
+
+    /// Like `get_mut`, but the panic message also includes the key, and with the `audit` feature,
+    /// the `Debug` form of the key is recorded along with the borrow site.
+    #[track_caller]
+    pub fn get_mut_debug<Q: ?Sized>(&mut self, k: &Q) -> Option<&'a mut V>
+        where K: Borrow<Q>, Q: Hash + Eq + fmt::Debug
+    {
+        let index = self.used;
+        match self.try_get_mut(k, index) {
+            Ok(v) => {
+                self.audit.describe_last(k);
+                Some(v)
+            },
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail_with_key(e, k),
+        }
+    }
+
+    /// Where each of the references handed out so far was borrowed, in order.
+    #[cfg(feature = "audit")]
+    pub fn borrow_sites(&self) -> &[BorrowSite] {
+        &self.audit.sites
+    }
+
+    #[track_caller]
+    fn try_get_mut<Q: ?Sized>(&mut self, k: &Q, index: usize) -> Result<&'a mut V, MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
     {
         if self.used == self.buffer.len() {
-            panic!("Buffer space is depleted!");
+            return Err(MultiMutError::BufferDepleted { index });
         }
         unsafe {    // We introduce the unsafe block early, because we want to be extra safe.
-                    // We create a reference &V here, but there may already exist an earlier
-                    // reference &mut V. This should be fine, since we never access &V through
-                    // this reference before it has proven to be unique. However, in case the 
-                    // compiler jumps to conclusions based of mere *existence* of &V, we want
+                    // We create a reference &mut V here, but there may already exist an earlier
+                    // reference &mut V. This should be fine, since we never access the value through
+                    // this reference before it has proven to be unique. However, in case the
+                    // compiler jumps to conclusions based of mere *existence* of it, we want
                     // to have it exist only inside an unsafe block, to signal that the type
                     // system invariants may be temporarily broken.
-            let ptr = if let Some(v) = self.map.get(k) { v as *const V as *mut V } else { return None };
+            let ptr = if let Some(v) = self.map.get_mut(k) { v as *mut V } else { return Err(MultiMutError::NoSuchKey { index }) };
 
-            for old_ptr in &self.buffer[0..self.used] {
-                if ptr == *old_ptr {
-                    panic!("No aliased references allowed! This key has been already used.");
-                }
-            }
-            self.buffer[self.used] = ptr;
-            self.used += 1;
-    
-            Some(transmute(ptr))
+            self.track(ptr, index)?;
+
+            Ok(&mut *ptr)   // This is safe to do because track checked that ptr isn't in the buffer of the references
+                            // handed out so far, and the map stays borrowed mutably for 'a.
+        }
+    }
+
+    /// Like `get_mut`, but returns the key stored in the map alongside the value.
+    #[track_caller]
+    pub fn get_key_value_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<(&'a K, &'a mut V)>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        let index = self.used;
+        match self.try_get_key_value_mut(k, index) {
+            Ok(entry) => Some(entry),
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail(e),
+        }
+    }
+
+    /// Like `get_key_value_mut`, but the panic message also includes the key. See `get_mut_debug`.
+    #[track_caller]
+    pub fn get_key_value_mut_debug<Q: ?Sized>(&mut self, k: &Q) -> Option<(&'a K, &'a mut V)>
+        where K: Borrow<Q>, Q: Hash + Eq + fmt::Debug
+    {
+        let index = self.used;
+        match self.try_get_key_value_mut(k, index) {
+            Ok(entry) => {
+                self.audit.describe_last(k);
+                Some(entry)
+            },
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail_with_key(e, k),
+        }
+    }
+
+    #[track_caller]
+    fn try_get_key_value_mut<Q: ?Sized>(&mut self, k: &Q, index: usize) -> Result<(&'a K, &'a mut V), MultiMutError>
+        where K: Borrow<Q>, Q: Hash + Eq
+    {
+        if self.used == self.buffer.len() {
+            return Err(MultiMutError::BufferDepleted { index });
+        }
+        unsafe {    // See try_get_mut for why the unsafe block starts this early.
+            let (key, ptr) = if let Some(entry) = hash_key_value_ptrs(self.map, k) { entry } else { return Err(MultiMutError::NoSuchKey { index }) };
+
+            self.track(ptr, index)?;
+
+            Ok((&*key, &mut *ptr))  // This is safe to do because track checked that ptr wasn't handed out before.
+                                    // Keys are only ever handed out shared.
+        }
+    }
+
+    /// Checks that `ptr` doesn't alias any of the earlier handed out references, and records it.
+    #[track_caller]
+    fn track(&mut self, ptr: *mut V, index: usize) -> Result<(), MultiMutError> {
+        if let Some(slot) = self.buffer[0..self.used].iter().position(|&old_ptr| old_ptr == ptr) {
+            self.audit.conflict(slot);
+            return Err(MultiMutError::Aliased { index });
         }
+        self.buffer[self.used] = ptr;
+        self.used += 1;
+        self.audit.record();
+        Ok(())
     }
 
+    #[track_caller]
     pub fn mut_ref<Q: ?Sized>(&mut self, k: &Q) -> &'a mut V
-        where K: Borrow<Q>, Q: Ord {
-            match self.get_mut(k) {
-                Some(v) => v,
-                None => panic!("No such key!"),
+        where K: Borrow<Q>, Q: Hash + Eq {
+            let index = self.used;
+            match self.try_get_mut(k, index) {
+                Ok(v) => v,
+                Err(e) => self.audit.fail(e),
+            }
+        }
+
+    /// Like `mut_ref`, but the panic message also includes the key. See `get_mut_debug`.
+    #[track_caller]
+    pub fn mut_ref_debug<Q: ?Sized>(&mut self, k: &Q) -> &'a mut V
+        where K: Borrow<Q>, Q: Hash + Eq + fmt::Debug {
+            let index = self.used;
+            match self.try_get_mut(k, index) {
+                Ok(v) => {
+                    self.audit.describe_last(k);
+                    v
+                },
+                Err(e) => self.audit.fail_with_key(e, k),
             }
         }
 }
 
-pub struct BTreeMapMultiMutIter<'a, Q: ?Sized + 'a, K: 'a, V: 'a>
-        where K: Borrow<Q> + Ord, Q: Ord
+/// The iterator returned by `iter_multi_mut`, over a slice of keys.
+pub type HashMapMultiMutIter<'a, Q, K, V> = HashMapMultiMutIterBy<'a, Q, Cloned<slice::Iter<'a, &'a Q>>, K, V>;
+
+/// The iterator returned by `iter_multi_mut_by`, over any iterator of keys.
+pub struct HashMapMultiMutIterBy<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
 {
-    mut_wrapper: BTreeMapMutWrapper<'a, K, V>,
-    keys: Iter<'a, &'a Q>,
+    mut_wrapper: HashMapMutWrapper<'a, K, V>,
+    keys: I,
+    key_fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>,
+    key_type: PhantomData<fn(&Q)>,
 }
 
-impl<'a, Q: ?Sized, K, V> Iterator for BTreeMapMultiMutIter<'a, Q, K, V>
-        where K: Borrow<Q> + Ord, Q: Ord
+impl<'a, Q: ?Sized, I, K, V> Iterator for HashMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
 {
     type Item = &'a mut V;
 
+    /// With the `audit` feature, the recorded borrow site is the caller of `next`: the line of an explicit `next()` call,
+    /// but a location inside `std` when the iterator is driven by a `for` loop or an adapter.
+    #[track_caller]
     fn next(&mut self) -> Option<Self::Item> {
         if self.mut_wrapper.used == self.mut_wrapper.buffer.len() { return None };
         match self.keys.next() {
-            Some(q) => { Some(self.mut_wrapper.mut_ref(q)) },   // Why use panicking mut_ref? Since we provide the keys up front with iterator,
-                                                                // it's likely that a non-existant key is a bug.
+            Some(q) => {    // Why panic like mut_ref? Since we provide the keys up front with iterator,
+                            // it's likely that a non-existant key is a bug.
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
             None => None,
         }
-        
-    } 
-}
+    }
 
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let capacity = self.mut_wrapper.buffer.len() - self.mut_wrapper.used;
+        let (lower, upper) = self.keys.size_hint();
+        (min(lower, capacity), Some(upper.map_or(capacity, |upper| min(upper, capacity))))
+    }
+}
 
+impl<'a, Q: ?Sized, I, K, V> ExactSizeIterator for HashMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: ExactSizeIterator, I::Item: Borrow<Q>
+{}
 
+impl<'a, Q: ?Sized, I, K, V> FusedIterator for HashMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: FusedIterator, I::Item: Borrow<Q>
+{}
 
-#[cfg(test)]
-mod tests_hash {
+impl<'a, Q: ?Sized, I, K, V> DoubleEndedIterator for HashMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: DoubleEndedIterator + ExactSizeIterator, I::Item: Borrow<Q>
+{
+    #[track_caller]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        // The keys that don't fit in the buffer would never be yielded from the front, so they are skipped here too.
+        let capacity = self.mut_wrapper.buffer.len() - self.mut_wrapper.used;
+        let excess = self.keys.len().saturating_sub(capacity);
+        match self.keys.nth_back(excess) {     // Not Option::map, since #[track_caller] doesn't reach through closures.
+            Some(q) => {
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
+            None => None,
+        }
+    }
+}
 
-    use std::collections::HashMap;
-    use HashMapMultiMut;
-    use std::ptr::null_mut;
+impl<'a, Q: ?Sized, I, K, V> HashMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    /// Includes the key in the panic messages, and with the `audit` feature, records the `Debug` form of every key
+    /// along with its borrow site, like the wrapper's `mut_ref_debug` does.
+    pub fn debug_keys(mut self) -> Self
+        where Q: fmt::Debug
+    {
+        self.key_fmt = Some(<Q as fmt::Debug>::fmt);
+        self
+    }
 
-    fn populate_hashmap() -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        map.insert("key_one".into(), "value_one".into());
-        map.insert("key_two".into(), "value_two".into());
-        map.insert("key_three".into(), "value_three".into());
-        map.insert("key_four".into(), "value_four".into());
-        map.insert("key_five".into(), "value_five".into());
-        map.insert("key_six".into(), "value_six".into());
-        map
+    /// Collects the next `N` references into an array. Returns `None` if the iterator runs out before that.
+    #[track_caller]
+    pub fn collect_array<const N: usize>(mut self) -> Option<[&'a mut V; N]> {
+        let mut items = [(); N].map(|_| None);
+        for item in &mut items {    // Not array::map, since #[track_caller] doesn't reach through closures.
+            *item = self.next();
+        }
+        if items.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(items.map(Option::unwrap))
     }
+}
 
-    #[test]
-    fn test_pair_success() {
+pub struct HashMapMultiMutEntries<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    mut_wrapper: HashMapMutWrapper<'a, K, V>,
+    keys: I,
+    key_fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>,
+    key_type: PhantomData<fn(&Q)>,
+}
+
+impl<'a, Q: ?Sized, I, K, V> Iterator for HashMapMultiMutEntries<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    type Item = (&'a K, &'a mut V);
+
+    /// Records the same borrow site as [`HashMapMultiMutIter::next`].
+    #[track_caller]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.mut_wrapper.used == self.mut_wrapper.buffer.len() { return None };
+        match self.keys.next() {
+            Some(q) => {
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_key_value_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
+            None => None,
+        }
+    }
+}
+
+impl<'a, Q: ?Sized, I, K, V> HashMapMultiMutEntries<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    /// Includes the key in the panic messages, and records it along with the borrow site. See [`HashMapMultiMutIter::debug_keys`].
+    pub fn debug_keys(mut self) -> Self
+        where Q: fmt::Debug
+    {
+        self.key_fmt = Some(<Q as fmt::Debug>::fmt);
+        self
+    }
+}
+
+pub struct HashMapMultiMutTryIter<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    mut_wrapper: HashMapMutWrapper<'a, K, V>,
+    keys: Enumerate<I>,
+    handed_out: Vec<I::Item>,
+    key_type: PhantomData<fn(&Q)>,
+}
+
+impl<'a, Q: ?Sized, I, K, V> Iterator for HashMapMultiMutTryIter<'a, Q, I, K, V>
+        where K: Borrow<Q> + Hash + Eq, Q: Hash + Eq, I: Iterator, I::Item: Borrow<Q>
+{
+    type Item = Result<&'a mut V, MultiMutError>;
+
+    #[track_caller]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, q) = self.keys.next()?;
+        // Looking up a key whose value was already handed out would invalidate the reference to it,
+        // so a repeated key is recognised by comparing it with the earlier keys instead.
+        if self.handed_out.iter().any(|earlier| Borrow::<Q>::borrow(earlier) == q.borrow()) {
+            return Some(Err(MultiMutError::Aliased { index }));
+        }
+        match self.mut_wrapper.try_get_mut(q.borrow(), index) {
+            Ok(v) => {
+                self.handed_out.push(q);
+                Some(Ok(v))
+            },
+            Err(e @ MultiMutError::Aliased { .. }) => self.mut_wrapper.audit.fail(e),   // Different keys led to the same value,
+                                                                                        // so the Borrow impl of the key is broken.
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/* BTreeMap */
+
+/// Endows HashMap with extension methods that help getting multiple mutable references to the values contained in it.
+/// Runtime-checking is done to ensure that this is safe: the returned mutable references are guaranteed not to alias.
+pub trait BTreeMapMultiMut {
+    type Value;
+    type Key: Ord;
+
+    fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut Self::Value, &mut Self::Value)>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Like `pair_mut`, but the panic message also includes the offending key.
+    fn pair_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Ord + fmt::Debug;
+
+    /// Like `get_pair_mut`, but if both keys point to the same value, returns that value as `PairOrSingle::Same`
+    /// instead of `None`.
+    fn get_pair_or_single_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<PairOrSingle<&mut Self::Value>>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut Self::Value, &mut Self::Value, &mut Self::Value)>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut Self::Value, &mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Like `triple_mut`, but the panic message also includes the offending key.
+    fn triple_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut Self::Value, &mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Ord + fmt::Debug;
+
+    /// Returns a tuple of the values of a tuple of keys, e.g. `map.get_tuple_mut(("a", &b, &*c))`.
+    /// Unlike with the other methods, every key can be of a different borrowed form.
+    fn get_tuple_mut<'a, T>(&'a mut self, keys: T) -> Result<T::Values, MultiMutError>
+        where T: KeyTuple<'a, Self>;
+
+    /// Returns a struct of the values of a struct of keys, e.g. `let TransferMut { src, dst, fee } = map.get_named_mut(&keys)?;`.
+    /// Naming the keys keeps them from getting mixed up, like they easily do in tuples. See `MultiMutKeys`.
+    fn get_named_mut<'a, T>(&'a mut self, keys: &T) -> Result<T::Values, MultiMutError>
+        where T: MultiMutKeys<'a, Self>;
+
+    /// Returns the values of all `keys`, when their number is only known at runtime. Missing and repeated keys
+    /// are reported as errors. Unlike `multi_mut`, this doesn't need a buffer, but it allocates.
+    #[cfg(feature = "alloc")]
+    fn get_many_mut_vec<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<Vec<&mut Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Returns the values of all `keys`, with `None` for the keys that don't exist. The values that exist are checked not to alias.
+    fn get_many_mut_opt<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[Option<&mut Self::Value>; N], AliasError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Like `get_pair_mut`, but returns the keys stored in the map alongside the values.
+    #[allow(clippy::type_complexity)]
+    fn get_pair_key_value_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<((&Self::Key, &mut Self::Value), (&Self::Key, &mut Self::Value))>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut Self::Value]) -> BTreeMapMutWrapper<'a, Self::Key, Self::Value>;
+
+    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut Self::Value]) -> BTreeMapMultiMutIter<'a, Q, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Like `iter_multi_mut`, but takes anything that iterates over keys: an array of `&Q`, a `Vec<String>`, or a lazily
+    /// evaluated iterator. The keys are only borrowed while they are looked up, so their lifetime is independent of the
+    /// returned references. If the key type implements `Borrow` for several types (as `String` does), name the borrowed
+    /// type explicitly: `map.iter_multi_mut_by::<str, _>(keys, &mut buffer)`. Like `multi_mut`, this is only accepted by
+    /// Miri under the Tree Borrows model; see `BTreeMapMutWrapper`.
+    fn iter_multi_mut_by<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> BTreeMapMultiMutIterBy<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Like `iter_multi_mut`, but yields the keys stored in the map alongside the values. See `BTreeMapMultiMutEntries`
+    /// for how Miri treats it.
+    fn iter_multi_mut_entries<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> BTreeMapMultiMutEntries<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Fetches the values of all `keys` at once, handling keys that point to the same value according to `policy`.
+    /// Unlike the other methods, this one allocates, but it doesn't need a buffer.
+    fn multi_mut_batch<'a, Q: ?Sized, I>(&'a mut self, keys: I, policy: DuplicatePolicy) -> Result<MultiMutBatch<'a, Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Fetches the values of all `keys` and hands them out as a parallel iterator. Like `iter_multi_mut`, this panics
+    /// if a key doesn't exist or if several keys point to the same value; the check is done once, before any work is started.
+    #[cfg(feature = "rayon")]
+    fn par_iter_multi_mut<Q: ?Sized, I>(&mut self, keys: I) -> rayon::vec::IntoIter<&mut Self::Value>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>, Self::Value: Send;
+
+    /// Fetches the values for several groups of keys, one group per worker thread. All keys are checked to be distinct
+    /// across all the groups, so each group can be moved to its own thread, for example with `std::thread::scope`.
+    /// Panics if a key doesn't exist or if several keys point to the same value.
+    fn split_for_threads<Q: ?Sized, I, G>(&mut self, keys_per_worker: I) -> Vec<Vec<&mut Self::Value>>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator<Item = G>, G: IntoIterator, G::Item: Borrow<Q>;
+
+    /// Follows each `(outer key, inner key)` path into the nested map stored in this map, and returns the values at the ends
+    /// of the paths. Paths may share the outer key, as long as the values at their ends are distinct.
+    /// The error carries the index of the offending path.
+    #[allow(clippy::type_complexity)]
+    fn get_paths_mut<Q: ?Sized, R: ?Sized, const N: usize>(&mut self, paths: [(&Q, &R); N]) -> Result<[&mut <Self::Value as LookupMut<R>>::Value; N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, Self::Value: LookupMut<R>;
+
+    /// Like `pair_mut`, but inserts the values created by `f` for the keys that don't exist yet.
+    /// Panics if the keys are equal; in that case nothing is inserted.
+    fn pair_entry_or_insert_with<Q: ?Sized, F>(&mut self, k_1: &Q, k_2: &Q, f: F) -> (&mut Self::Value, &mut Self::Value)
+        where Self::Key: Borrow<Q>, Q: Ord + ToOwned<Owned = Self::Key>, F: FnMut() -> Self::Value;
+
+    /// Returns the values of all `keys`, first inserting the values created by `f` for the keys that don't exist yet.
+    /// If the keys aren't distinct, returns an error without inserting anything.
+    fn get_many_or_insert_with<Q: ?Sized, F, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<[&mut Self::Value; N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord + ToOwned<Owned = Self::Key>, F: FnMut() -> Self::Value;
+
+    /// Swaps the values of two keys.
+    fn swap_values<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Moves the value of each key to the next key, and the value of the last key to the first one.
+    fn rotate_values<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Moves the values around so that `keys[i]` ends up with the value `keys[perm[i]]` had.
+    /// Returns `MultiMutError::InvalidPermutation` if `perm` isn't a permutation of `0..keys.len()`.
+    fn permute_values<Q: ?Sized>(&mut self, keys: &[&Q], perm: &[usize]) -> Result<(), MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Removes the value of `src` from the map and passes it to `f` along with the value of `dst`, returning what `f` returns.
+    /// If either key is missing or both point to the same value, returns an error without changing the map;
+    /// `src` has the index 0 and `dst` the index 1.
+    fn merge_into<Q: ?Sized, F, R>(&mut self, src: &Q, dst: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, F: FnOnce(&mut Self::Value, Self::Value) -> R;
+
+    /// Removes all `keys` from the map and returns the stored keys with their values. Every key is checked to exist
+    /// and to be distinct before anything is removed, so on error the map is left untouched.
+    #[allow(clippy::type_complexity)]
+    fn remove_many<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[(Self::Key, Self::Value); N], MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord;
+
+    /// Runs `f` on the values of all `keys` as a transaction: if `f` returns an error or panics, the values are restored
+    /// to what they were before. The values are snapshotted by cloning them.
+    fn transaction<Q: ?Sized, F, T, E, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<T, TransactionError<E>>
+        where Self::Key: Borrow<Q>, Q: Ord, Self::Value: Clone, F: FnOnce([&mut Self::Value; N]) -> Result<T, E>;
+
+    /// Like `transaction`, but the values are snapshotted with `save` and restored with `restore`,
+    /// for values that can't be cloned or that have a cheaper way to undo the changes.
+    fn transaction_with<Q: ?Sized, S, Save, Restore, F, T, E, const N: usize>(&mut self, keys: [&Q; N], save: Save, restore: Restore, f: F) -> Result<T, TransactionError<E>>
+        where Self::Key: Borrow<Q>, Q: Ord, Save: FnMut(&Self::Value) -> S, Restore: FnMut(&mut Self::Value, S),
+              F: FnOnce([&mut Self::Value; N]) -> Result<T, E>;
+
+    /// Passes the values of the two keys to `f` and returns what it returns. The references can't escape the closure.
+    fn with_pair_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, F: FnOnce(&mut Self::Value, &mut Self::Value) -> R;
+
+    /// Passes the values of the three keys to `f` and returns what it returns. The references can't escape the closure.
+    fn with_triple_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q, f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, F: FnOnce(&mut Self::Value, &mut Self::Value, &mut Self::Value) -> R;
+
+    /// Passes the values of all `keys` to `f` and returns what it returns. The references can't escape the closure.
+    fn with_many_mut<Q: ?Sized, F, R, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<R, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, F: FnOnce([&mut Self::Value; N]) -> R;
+
+    /// Starting from `start`, follows the keys returned by `next` from value to value until it returns `None`,
+    /// and returns the values along the chain. `next` returns the keys owned, so that none of them is borrowed
+    /// from a value in the map while the map is looked into. A chain that loops back to an already visited value is reported as
+    /// `MultiMutError::Aliased`, and a link to a missing key as `MultiMutError::NoSuchKey`; the index is the position in the chain.
+    fn follow_chain_mut<Q: ?Sized, F>(&mut self, start: &Q, next: F) -> Result<Vec<&mut Self::Value>, MultiMutError>
+        where Self::Key: Borrow<Q>, Q: Ord, F: FnMut(&Self::Value) -> Option<Self::Key>;
+
+    /// Returns the value of `center` along with the values of its neighbors. Missing neighbors are returned as `None`.
+    /// Returns `None` if `center` is missing, or if any two of the keys point to the same value.
+    #[allow(clippy::type_complexity)]
+    fn get_with_neighbors_mut<Q: ?Sized, I>(&mut self, center: &Q, neighbor_keys: I) -> Option<(&mut Self::Value, Vec<Option<&mut Self::Value>>)>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>;
+
+    /// Like `iter_multi_mut`, but doesn't panic: missing keys, repeated keys and keys that don't fit in the buffer
+    /// are reported as errors carrying the index of the key, and the iteration continues with the next key.
+    /// The repeated keys are told apart by comparing them with the earlier keys, so they aren't looked up again.
+    /// The other keys are, after references have been handed out, so Miri only accepts this under Tree Borrows; see
+    /// `BTreeMapMutWrapper`.
+    fn try_iter_multi_mut<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut Self::Value]) -> BTreeMapMultiMutTryIter<'a, Q, I::IntoIter, Self::Key, Self::Value>
+        where Self::Key: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>;
+}
+
+
+impl<K: Ord, V> BTreeMapMultiMut for BTreeMap<K, V> {
+    type Value = V;
+    type Key = K;
+
+    fn get_pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<(&mut V, &mut V)>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptr_1 = btree_value_ptr(self, k_1);
+        let ptr_2 = btree_value_ptr(self, k_2);
+
+        match (ptr_1, ptr_2) {
+            (Some(ptr_1), Some(ptr_2)) => {
+
+                if ptr_1 == ptr_2 {
+                    None
+                } else {
+                    unsafe { Some((&mut *ptr_1, &mut *ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias,
+                                                                // and this function consumed a &mut self, which locks the HashMap so that
+                                                                // no further aliasing references will be created during the lifetime of these
+                                                                // references. Looking up k_2 doesn't invalidate ptr_1, see collect_batch.
+                }
+            },
+            _ => None,
+        }
+    }
+
+    #[track_caller]
+    fn pair_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut V, &mut V)
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptrs = [k_1, k_2].map(|k| btree_value_ptr(self, k));
+        expect_disjoint(&ptrs);
+        let [ptr_1, ptr_2] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2) } // This is safe to do because expect_disjoint checked that ptr_1 and ptr_2 don't alias,
+                                              // and this function consumed a &mut self, which locks the HashMap so that
+                                              // no further aliasing references will be created during the lifetime of these
+                                              // references.
+    }
+
+    #[track_caller]
+    fn pair_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> (&mut V, &mut V)
+        where K: Borrow<Q>, Q: Ord + fmt::Debug
+    {
+        let ptrs = [k_1, k_2].map(|k| btree_value_ptr(self, k));
+        expect_disjoint_debug(&ptrs, &[k_1, k_2]);
+        let [ptr_1, ptr_2] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2) } // This is safe to do for the same reasons as in pair_mut.
+    }
+
+    fn get_pair_or_single_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<PairOrSingle<&mut V>>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptr_1 = btree_value_ptr(self, k_1);
+        let ptr_2 = btree_value_ptr(self, k_2);
+
+        match (ptr_1, ptr_2) {
+            (Some(ptr_1), Some(ptr_2)) => {
+
+                if ptr_1 == ptr_2 {
+                    unsafe { Some(PairOrSingle::Same(&mut *ptr_2)) }   // This is safe to do because only one reference is made
+                                                                        // out of the two equal pointers. Looking up k_2 invalidated ptr_1.
+                } else {
+                    unsafe { Some(PairOrSingle::Pair(&mut *ptr_1, &mut *ptr_2)) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias.
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn get_triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> Option<(&mut V, &mut V, &mut V)>
+        where K: Borrow<Q>, Q: Ord
+    {
+
+        let ptr_1 = btree_value_ptr(self, k_1);
+        let ptr_2 = btree_value_ptr(self, k_2);
+        let ptr_3 = btree_value_ptr(self, k_3);
+
+        match (ptr_1, ptr_2, ptr_3) {
+            (Some(ptr_1), Some(ptr_2), Some(ptr_3)) => {
+
+                if ptr_1 == ptr_2 || ptr_2 == ptr_3 || ptr_1 == ptr_3 {
+                    None
+                } else {
+                    unsafe { Some((&mut *ptr_1, &mut *ptr_2, &mut *ptr_3)) }
+                        // This is safe to do because we checked that ptr_1, ptr_2 and ptr_3 don't alias,
+                        // and this function consumed a &mut self, which locks the HashMap so that
+                        // no further aliasing references will be created during the lifetime of these
+                        // references. The later lookups don't invalidate the earlier pointers, see collect_batch.
+                }
+            },
+            _ => None,
+        }
+    }
+
+    #[track_caller]
+    fn triple_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut V, &mut V, &mut V)
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptrs = [k_1, k_2, k_3].map(|k| btree_value_ptr(self, k));
+        expect_disjoint(&ptrs);
+        let [ptr_1, ptr_2, ptr_3] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2, &mut *ptr_3) }
+            // This is safe to do because expect_disjoint checked that ptr_1, ptr_2 and ptr_3 don't alias,
+            // and this function consumed a &mut self, which locks the HashMap so that
+            // no further aliasing references will be created during the lifetime of these
+            // references.
+    }
+
+    #[track_caller]
+    fn triple_mut_debug<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q) -> (&mut V, &mut V, &mut V)
+        where K: Borrow<Q>, Q: Ord + fmt::Debug
+    {
+        let ptrs = [k_1, k_2, k_3].map(|k| btree_value_ptr(self, k));
+        expect_disjoint_debug(&ptrs, &[k_1, k_2, k_3]);
+        let [ptr_1, ptr_2, ptr_3] = ptrs.map(Option::unwrap);
+
+        unsafe { (&mut *ptr_1, &mut *ptr_2, &mut *ptr_3) } // This is safe to do for the same reasons as in triple_mut.
+    }
+
+    #[cfg(feature = "alloc")]
+    fn get_many_mut_vec<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<Vec<&mut V>, MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        self.multi_mut_batch(keys.iter().cloned(), DuplicatePolicy::Error).map(|batch| batch.values)
+    }
+
+    fn get_many_mut_opt<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[Option<&mut V>; N], AliasError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptrs = keys.map(|k| btree_value_ptr(self, k));
+
+        for (index, ptr) in ptrs.iter().enumerate() {
+            if ptr.is_some() {
+                if let Some(first) = ptrs[..index].iter().position(|old_ptr| old_ptr == ptr) {
+                    return Err(AliasError { first, index });
+                }
+            }
+        }
+        unsafe { Ok(ptrs.map(|ptr| ptr.map(|ptr| &mut *ptr))) }  // This is safe to do because we checked that the pointers of the keys
+                                                                // that exist don't alias, and the missing ones don't become references.
+    }
+
+    fn get_tuple_mut<'a, T>(&'a mut self, keys: T) -> Result<T::Values, MultiMutError>
+        where T: KeyTuple<'a, Self>
+    {
+        keys.get_mut_from(self)
+    }
+
+    fn get_named_mut<'a, T>(&'a mut self, keys: &T) -> Result<T::Values, MultiMutError>
+        where T: MultiMutKeys<'a, Self>
+    {
+        keys.get_named_mut_from(self)
+    }
+
+    fn get_pair_key_value_mut<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Option<((&K, &mut V), (&K, &mut V))>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let entry_1 = btree_key_value_ptrs(self, k_1);
+        let entry_2 = btree_key_value_ptrs(self, k_2);
+
+        match (entry_1, entry_2) {
+            (Some((key_1, ptr_1)), Some((key_2, ptr_2))) => {
+
+                if ptr_1 == ptr_2 {
+                    None
+                } else {
+                    unsafe { Some(((&*key_1, &mut *ptr_1), (&*key_2, &mut *ptr_2))) } // This is safe to do because we checked that ptr_1 and ptr_2 don't alias.
+                                                                                  // The keys are only handed out shared, and a key is stored
+                                                                                  // apart from its value, so it doesn't overlap either of them.
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn multi_mut<'a>(&'a mut self, buffer: &'a mut [*mut V]) -> BTreeMapMutWrapper<'a, K, V>
+    {
+        BTreeMapMutWrapper { used: 0, map: self, buffer, audit: BorrowAudit::default() }
+    }
+
+    fn iter_multi_mut<'a, Q: ?Sized>(&'a mut self, keys: &'a [&'a Q], buffer: &'a mut [*mut V]) -> BTreeMapMultiMutIter<'a, Q, K, V>
+        where K: Borrow<Q>, Q: Ord
+    {
+        self.iter_multi_mut_by(keys.iter().cloned(), buffer)
+    }
+
+    fn iter_multi_mut_by<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> BTreeMapMultiMutIterBy<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        BTreeMapMultiMutIterBy { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter(), key_fmt: None, key_type: PhantomData }
+    }
+
+    fn iter_multi_mut_entries<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> BTreeMapMultiMutEntries<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        BTreeMapMultiMutEntries { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter(), key_fmt: None, key_type: PhantomData }
+    }
+
+    #[track_caller]
+    fn multi_mut_batch<'a, Q: ?Sized, I>(&'a mut self, keys: I, policy: DuplicatePolicy) -> Result<MultiMutBatch<'a, V>, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        let ptrs = keys.into_iter().map(|q| btree_value_ptr(self, q.borrow()));
+        unsafe { collect_batch(ptrs, policy) }    // This is safe to do because the pointers come from btree_value_ptr, collect_batch makes at most
+                                                    // one reference per value, and the map stays borrowed mutably for 'a.
+    }
+
+    #[cfg(feature = "rayon")]
+    #[track_caller]
+    fn par_iter_multi_mut<Q: ?Sized, I>(&mut self, keys: I) -> rayon::vec::IntoIter<&mut V>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>, V: Send
+    {
+        match self.multi_mut_batch(keys, DuplicatePolicy::Panic) {
+            Ok(batch) => batch.values.into_par_iter(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[track_caller]
+    fn split_for_threads<Q: ?Sized, I, G>(&mut self, keys_per_worker: I) -> Vec<Vec<&mut V>>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator<Item = G>, G: IntoIterator, G::Item: Borrow<Q>
+    {
+        let groups = keys_per_worker.into_iter()
+            .map(|group| group.into_iter().map(|q| btree_value_ptr(self, q.borrow())).collect())
+            .collect();
+        unsafe { collect_groups(groups) } // This is safe to do because collect_groups checks the pointers of all the groups against
+                                            // each other, so no value ends up in two groups, and the map stays borrowed mutably.
+    }
+
+    fn get_paths_mut<Q: ?Sized, R: ?Sized, const N: usize>(&mut self, paths: [(&Q, &R); N]) -> Result<[&mut V::Value; N], MultiMutError>
+        where K: Borrow<Q>, Q: Ord, V: LookupMut<R>
+    {
+        unsafe {   // See HashMapMutWrapper::try_get_mut for why the unsafe block starts this early: paths sharing an outer key
+                    // create a new &mut to the inner map while pointers to values in it already exist.
+            let ptrs = paths.map(|(q, r)| {
+                let inner = btree_value_ptr(self, q)?;
+                (*inner).lookup_mut(r).map(|v| v as *mut V::Value)
+            });
+            collect_disjoint(ptrs)  // This is safe to do because collect_disjoint checks that the values at the ends of the paths don't alias.
+                                    // The inner maps are reached through raw pointers, and looking up a second path in the same
+                                    // inner map reborrows that map, not the value found for the first path.
+        }
+    }
+
+    #[track_caller]
+    fn pair_entry_or_insert_with<Q: ?Sized, F>(&mut self, k_1: &Q, k_2: &Q, mut f: F) -> (&mut V, &mut V)
+        where K: Borrow<Q>, Q: Ord + ToOwned<Owned = K>, F: FnMut() -> V
+    {
+        if k_1 == k_2 {
+            panic!("{}", AliasError { first: 0, index: 1 })
+        }
+        // Inserting may move the values around, so all the insertions are done before any pointers are taken.
+        for &k in &[k_1, k_2] {
+            if !self.contains_key(k) {
+                self.insert(k.to_owned(), f());
+            }
+        }
+        self.pair_mut(k_1, k_2)
+    }
+
+    fn get_many_or_insert_with<Q: ?Sized, F, const N: usize>(&mut self, keys: [&Q; N], mut f: F) -> Result<[&mut V; N], MultiMutError>
+        where K: Borrow<Q>, Q: Ord + ToOwned<Owned = K>, F: FnMut() -> V
+    {
+        for (index, k) in keys.iter().enumerate() {
+            if keys[..index].contains(k) {
+                return Err(MultiMutError::Aliased { index });
+            }
+        }
+        // Inserting may move the values around, so all the insertions are done before any pointers are taken.
+        for &k in &keys {
+            if !self.contains_key(k) {
+                self.insert(k.to_owned(), f());
+            }
+        }
+        let ptrs = keys.map(|k| btree_value_ptr(self, k));
+        unsafe { collect_disjoint(ptrs) } // This is safe to do because nothing is inserted after the pointers are taken,
+                                            // and collect_disjoint checks that they don't alias.
+    }
+
+    fn swap_values<Q: ?Sized>(&mut self, k_1: &Q, k_2: &Q) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptrs = [k_1, k_2].map(|k| btree_value_ptr(self, k));
+        let [v_1, v_2] = unsafe { collect_disjoint(ptrs)? };  // This is safe to do because collect_disjoint checks that the pointers don't alias.
+        mem::swap(v_1, v_2);
+        Ok(())
+    }
+
+    fn rotate_values<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let perm: Vec<usize> = (0..keys.len()).map(|i| (i + keys.len() - 1) % keys.len()).collect();
+        self.permute_values(keys, &perm)
+    }
+
+    fn permute_values<Q: ?Sized>(&mut self, keys: &[&Q], perm: &[usize]) -> Result<(), MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let mut values = self.multi_mut_batch(keys.iter().cloned(), DuplicatePolicy::Error)?.values;
+        permute_in_place(&mut values, perm)
+    }
+
+    fn merge_into<Q: ?Sized, F, R>(&mut self, src: &Q, dst: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, F: FnOnce(&mut V, V) -> R
+    {
+        let ptrs = [src, dst].map(|k| btree_value_ptr(self, k));
+        check_disjoint(&ptrs)?;
+
+        let src_value = self.remove(src).expect("The key was checked to exist.");
+        let dst_value = self.get_mut(dst).expect("The key was checked to exist.");
+        Ok(f(dst_value, src_value))
+    }
+
+    fn remove_many<Q: ?Sized, const N: usize>(&mut self, keys: [&Q; N]) -> Result<[(K, V); N], MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let ptrs = keys.map(|k| btree_value_ptr(self, k));
+        check_disjoint(&ptrs)?;
+
+        Ok(keys.map(|k| self.remove_entry(k).expect("The key was checked to exist.")))
+    }
+
+    fn transaction<Q: ?Sized, F, T, E, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<T, TransactionError<E>>
+        where K: Borrow<Q>, Q: Ord, V: Clone, F: FnOnce([&mut V; N]) -> Result<T, E>
+    {
+        self.transaction_with(keys, V::clone, |v, snapshot| *v = snapshot, f)
+    }
+
+    fn transaction_with<Q: ?Sized, S, Save, Restore, F, T, E, const N: usize>(&mut self, keys: [&Q; N], save: Save, restore: Restore, f: F) -> Result<T, TransactionError<E>>
+        where K: Borrow<Q>, Q: Ord, Save: FnMut(&V) -> S, Restore: FnMut(&mut V, S),
+              F: FnOnce([&mut V; N]) -> Result<T, E>
+    {
+        let ptrs = keys.map(|k| btree_value_ptr(self, k));
+        let values = unsafe { collect_disjoint(ptrs) }.map_err(TransactionError::Keys)?; // This is safe to do because collect_disjoint checks
+                                                                                            // that the pointers don't alias.
+        run_transaction(values, save, restore, f).map_err(TransactionError::Aborted)
+    }
+
+    fn with_pair_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, F: FnOnce(&mut V, &mut V) -> R
+    {
+        self.with_many_mut([k_1, k_2], |[v_1, v_2]| f(v_1, v_2))
+    }
+
+    fn with_triple_mut<Q: ?Sized, F, R>(&mut self, k_1: &Q, k_2: &Q, k_3: &Q, f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, F: FnOnce(&mut V, &mut V, &mut V) -> R
+    {
+        self.with_many_mut([k_1, k_2, k_3], |[v_1, v_2, v_3]| f(v_1, v_2, v_3))
+    }
+
+    fn with_many_mut<Q: ?Sized, F, R, const N: usize>(&mut self, keys: [&Q; N], f: F) -> Result<R, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, F: FnOnce([&mut V; N]) -> R
+    {
+        let ptrs = keys.map(|k| btree_value_ptr(self, k));
+        let values = unsafe { collect_disjoint(ptrs)? };  // This is safe to do because collect_disjoint checks that the pointers don't alias,
+                                                            // and the references can't outlive the call to f.
+        Ok(f(values))
+    }
+
+    fn follow_chain_mut<Q: ?Sized, F>(&mut self, start: &Q, mut next: F) -> Result<Vec<&mut V>, MultiMutError>
+        where K: Borrow<Q>, Q: Ord, F: FnMut(&V) -> Option<K>
+    {
+        let mut chain: Vec<*mut V> = Vec::new();
+        unsafe {   // See HashMapMutWrapper::try_get_mut for why the unsafe block starts this early: the values
+                    // are read through the pointers in between the lookups.
+            let mut ptr = match btree_value_ptr(self, start) { Some(ptr) => ptr, None => return Err(MultiMutError::NoSuchKey { index: 0 }) };
+            loop {
+                let index = chain.len();
+                if chain.contains(&ptr) {
+                    return Err(MultiMutError::Aliased { index });
+                }
+                chain.push(ptr);
+                ptr = match next(&*ptr) {
+                    Some(k) => match btree_value_ptr::<K, V, K>(self, &k) { Some(ptr) => ptr, None => return Err(MultiMutError::NoSuchKey { index: index + 1 }) },
+                    None => break,
+                };
+            }
+            Ok(chain.into_iter().map(|ptr| &mut *ptr).collect())  // This is safe to do because a value that was already in the chain
+                                                                    // ends the walk with an error, so every value is in the chain once.
+        }
+    }
+
+    fn get_with_neighbors_mut<Q: ?Sized, I>(&mut self, center: &Q, neighbor_keys: I) -> Option<(&mut V, Vec<Option<&mut V>>)>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        let center = btree_value_ptr(self, center)?;
+        let mut found = vec![center];
+        let mut neighbors = Vec::new();
+
+        for k in neighbor_keys {
+            let ptr = btree_value_ptr(self, k.borrow());
+            if let Some(ptr) = ptr {
+                if found.contains(&ptr) {
+                    return None;
+                }
+                found.push(ptr);
+            }
+            neighbors.push(ptr);
+        }
+
+        unsafe { Some((&mut *center, neighbors.into_iter().map(|ptr| ptr.map(|ptr| &mut *ptr)).collect())) }
+            // This is safe to do because every neighbor that exists was checked against the center
+            // and the earlier neighbors before it was added to `found`.
+    }
+
+    fn try_iter_multi_mut<'a, Q: ?Sized, I>(&'a mut self, keys: I, buffer: &'a mut [*mut V]) -> BTreeMapMultiMutTryIter<'a, Q, I::IntoIter, K, V>
+        where K: Borrow<Q>, Q: Ord, I: IntoIterator, I::Item: Borrow<Q>
+    {
+        BTreeMapMultiMutTryIter { mut_wrapper: self.multi_mut(buffer), keys: keys.into_iter().enumerate(), handed_out: Vec::new(), key_type: PhantomData }
+    }
+
+}
+
+/// Looks up the key stored in the map and a pointer to the value.
+fn btree_key_value_ptrs<K, V, Q: ?Sized>(map: &mut BTreeMap<K, V>, k: &Q) -> Option<(*const K, *mut V)>
+        where K: Borrow<Q> + Ord, Q: Ord
+{
+    map.range_mut::<Q, _>((Bound::Included(k), Bound::Included(k))).next().map(|(key, v)| (key as *const K, v as *mut V))
+}
+
+/// Looks up a pointer to the value. Under Miri's Stacked Borrows model, a raw pointer taken from `range_mut` stays
+/// valid across the lookups of other keys, whereas one taken from `get_mut` doesn't. The references made from either
+/// are invalidated by the next lookup in the same node.
+fn btree_value_ptr<K, V, Q: ?Sized>(map: &mut BTreeMap<K, V>, k: &Q) -> Option<*mut V>
+        where K: Borrow<Q> + Ord, Q: Ord
+{
+    btree_key_value_ptrs(map, k).map(|(_, ptr)| ptr)
+}
+
+/// Hands out mutable references to the values of distinct keys one at a time. Returned by `multi_mut`.
+///
+/// Miri accepts the wrapper only under the Tree Borrows model (`-Zmiri-tree-borrows`), not under the default Stacked
+/// Borrows. A lookup in a `BTreeMap` reads the whole node it passes through, and Stacked Borrows counts that read as
+/// invalidating the mutable references already handed out from the same node. The methods of `BTreeMapMultiMut`
+/// that look up all the keys before handing out any references aren't affected.
+pub struct BTreeMapMutWrapper<'a, K: 'a, V: 'a>
+        where K: Ord
+{
+    used: usize,
+    map: &'a mut BTreeMap<K, V>,
+    buffer: &'a mut [*mut V],
+    audit: BorrowAudit,
+}
+
+// The buffer of raw pointers makes the wrapper !Send and !Sync by default. The pointers are only ever compared,
+// never dereferenced, so the wrapper is as thread-safe as the `&mut BTreeMap<K, V>` it holds.
+unsafe impl<'a, K, V> Send for BTreeMapMutWrapper<'a, K, V>
+        where K: Ord + Send, V: Send
+{}
+
+unsafe impl<'a, K, V> Sync for BTreeMapMutWrapper<'a, K, V>
+        where K: Ord + Sync, V: Sync
+{}
+
+/// Lists the active borrows. Their sites are only known with the `audit` feature.
+impl<'a, K, V> fmt::Debug for BTreeMapMutWrapper<'a, K, V>
+        where K: Ord
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("BTreeMapMutWrapper");
+        s.field("used", &self.used).field("capacity", &self.buffer.len());
+        #[cfg(feature = "audit")]
+        s.field("borrows", &self.audit.sites);
+        s.finish()
+    }
+}
+
+impl<'a, K, V> BTreeMapMutWrapper<'a, K, V>
+        where K: Ord
+{
+
+    #[track_caller]
+    pub fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&'a mut V>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let index = self.used;
+        match self.try_get_mut(k, index) {
+            Ok(v) => Some(v),
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail(e),
+        }
+    }
+
+    /// Like `get_mut`, but the panic message also includes the key, and with the `audit` feature,
+    /// the `Debug` form of the key is recorded along with the borrow site.
+    #[track_caller]
+    pub fn get_mut_debug<Q: ?Sized>(&mut self, k: &Q) -> Option<&'a mut V>
+        where K: Borrow<Q>, Q: Ord + fmt::Debug
+    {
+        let index = self.used;
+        match self.try_get_mut(k, index) {
+            Ok(v) => {
+                self.audit.describe_last(k);
+                Some(v)
+            },
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail_with_key(e, k),
+        }
+    }
+
+    /// Where each of the references handed out so far was borrowed, in order.
+    #[cfg(feature = "audit")]
+    pub fn borrow_sites(&self) -> &[BorrowSite] {
+        &self.audit.sites
+    }
+
+    #[track_caller]
+    fn try_get_mut<Q: ?Sized>(&mut self, k: &Q, index: usize) -> Result<&'a mut V, MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        if self.used == self.buffer.len() {
+            return Err(MultiMutError::BufferDepleted { index });
+        }
+        unsafe {    // We introduce the unsafe block early, because we want to be extra safe.
+                    // We create a reference &mut V here, but there may already exist an earlier
+                    // reference &mut V. This should be fine, since we never access the value through
+                    // this reference before it has proven to be unique. However, in case the
+                    // compiler jumps to conclusions based of mere *existence* of it, we want
+                    // to have it exist only inside an unsafe block, to signal that the type
+                    // system invariants may be temporarily broken.
+                    // Unlike in HashMap, a lookup in BTreeMap reads the whole node the value is stored in, the other values
+                    // included. Miri's Stacked Borrows model counts that as an access that invalidates the references
+                    // handed out from the same node so far, which the wrapper can't avoid without giving up on lookups.
+            let ptr = if let Some(ptr) = btree_value_ptr(self.map, k) { ptr } else { return Err(MultiMutError::NoSuchKey { index }) };
+
+            self.track(ptr, index)?;
+
+            Ok(&mut *ptr)   // This is safe to do because track checked that ptr isn't in the buffer of the references
+                            // handed out so far, and the map stays borrowed mutably for 'a.
+        }
+    }
+
+    /// Like `get_mut`, but returns the key stored in the map alongside the value.
+    #[track_caller]
+    pub fn get_key_value_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<(&'a K, &'a mut V)>
+        where K: Borrow<Q>, Q: Ord
+    {
+        let index = self.used;
+        match self.try_get_key_value_mut(k, index) {
+            Ok(entry) => Some(entry),
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail(e),
+        }
+    }
+
+    /// Like `get_key_value_mut`, but the panic message also includes the key. See `get_mut_debug`.
+    #[track_caller]
+    pub fn get_key_value_mut_debug<Q: ?Sized>(&mut self, k: &Q) -> Option<(&'a K, &'a mut V)>
+        where K: Borrow<Q>, Q: Ord + fmt::Debug
+    {
+        let index = self.used;
+        match self.try_get_key_value_mut(k, index) {
+            Ok(entry) => {
+                self.audit.describe_last(k);
+                Some(entry)
+            },
+            Err(MultiMutError::NoSuchKey { .. }) => None,
+            Err(e) => self.audit.fail_with_key(e, k),
+        }
+    }
+
+    #[track_caller]
+    fn try_get_key_value_mut<Q: ?Sized>(&mut self, k: &Q, index: usize) -> Result<(&'a K, &'a mut V), MultiMutError>
+        where K: Borrow<Q>, Q: Ord
+    {
+        if self.used == self.buffer.len() {
+            return Err(MultiMutError::BufferDepleted { index });
+        }
+        unsafe {    // See try_get_mut for why the unsafe block starts this early.
+            let (key, ptr) = if let Some(entry) = btree_key_value_ptrs(self.map, k) { entry } else { return Err(MultiMutError::NoSuchKey { index }) };
+
+            self.track(ptr, index)?;
+
+            Ok((&*key, &mut *ptr))  // This is safe to do because track checked that ptr wasn't handed out before.
+                                    // Keys are only ever handed out shared.
+        }
+    }
+
+    /// Checks that `ptr` doesn't alias any of the earlier handed out references, and records it.
+    #[track_caller]
+    fn track(&mut self, ptr: *mut V, index: usize) -> Result<(), MultiMutError> {
+        if let Some(slot) = self.buffer[0..self.used].iter().position(|&old_ptr| old_ptr == ptr) {
+            self.audit.conflict(slot);
+            return Err(MultiMutError::Aliased { index });
+        }
+        self.buffer[self.used] = ptr;
+        self.used += 1;
+        self.audit.record();
+        Ok(())
+    }
+
+    #[track_caller]
+    pub fn mut_ref<Q: ?Sized>(&mut self, k: &Q) -> &'a mut V
+        where K: Borrow<Q>, Q: Ord {
+            let index = self.used;
+            match self.try_get_mut(k, index) {
+                Ok(v) => v,
+                Err(e) => self.audit.fail(e),
+            }
+        }
+
+    /// Like `mut_ref`, but the panic message also includes the key. See `get_mut_debug`.
+    #[track_caller]
+    pub fn mut_ref_debug<Q: ?Sized>(&mut self, k: &Q) -> &'a mut V
+        where K: Borrow<Q>, Q: Ord + fmt::Debug {
+            let index = self.used;
+            match self.try_get_mut(k, index) {
+                Ok(v) => {
+                    self.audit.describe_last(k);
+                    v
+                },
+                Err(e) => self.audit.fail_with_key(e, k),
+            }
+        }
+}
+
+/// The iterator returned by `iter_multi_mut`, over a slice of keys. See `BTreeMapMultiMutIterBy`.
+pub type BTreeMapMultiMutIter<'a, Q, K, V> = BTreeMapMultiMutIterBy<'a, Q, Cloned<slice::Iter<'a, &'a Q>>, K, V>;
+
+/// The iterator returned by `iter_multi_mut_by`, over any iterator of keys. It looks up each key as it goes, through
+/// a `BTreeMapMutWrapper`, so it's subject to the same limitation under Miri's Stacked Borrows model.
+pub struct BTreeMapMultiMutIterBy<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    mut_wrapper: BTreeMapMutWrapper<'a, K, V>,
+    keys: I,
+    key_fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>,
+    key_type: PhantomData<fn(&Q)>,
+}
+
+impl<'a, Q: ?Sized, I, K, V> Iterator for BTreeMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    type Item = &'a mut V;
+
+    /// With the `audit` feature, the recorded borrow site is the caller of `next`: the line of an explicit `next()` call,
+    /// but a location inside `std` when the iterator is driven by a `for` loop or an adapter.
+    #[track_caller]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.mut_wrapper.used == self.mut_wrapper.buffer.len() { return None };
+        match self.keys.next() {
+            Some(q) => {    // Why panic like mut_ref? Since we provide the keys up front with iterator,
+                            // it's likely that a non-existant key is a bug.
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
+            None => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let capacity = self.mut_wrapper.buffer.len() - self.mut_wrapper.used;
+        let (lower, upper) = self.keys.size_hint();
+        (min(lower, capacity), Some(upper.map_or(capacity, |upper| min(upper, capacity))))
+    }
+}
+
+impl<'a, Q: ?Sized, I, K, V> ExactSizeIterator for BTreeMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: ExactSizeIterator, I::Item: Borrow<Q>
+{}
+
+impl<'a, Q: ?Sized, I, K, V> FusedIterator for BTreeMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: FusedIterator, I::Item: Borrow<Q>
+{}
+
+impl<'a, Q: ?Sized, I, K, V> DoubleEndedIterator for BTreeMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: DoubleEndedIterator + ExactSizeIterator, I::Item: Borrow<Q>
+{
+    /// Like `next`, this looks up a key after the earlier references have been handed out, so Miri only accepts it
+    /// under Tree Borrows. See `BTreeMapMutWrapper`.
+    #[track_caller]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        // The keys that don't fit in the buffer would never be yielded from the front, so they are skipped here too.
+        let capacity = self.mut_wrapper.buffer.len() - self.mut_wrapper.used;
+        let excess = self.keys.len().saturating_sub(capacity);
+        match self.keys.nth_back(excess) {     // Not Option::map, since #[track_caller] doesn't reach through closures.
+            Some(q) => {
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
+            None => None,
+        }
+    }
+}
+
+impl<'a, Q: ?Sized, I, K, V> BTreeMapMultiMutIterBy<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    /// Includes the key in the panic messages, and with the `audit` feature, records the `Debug` form of every key
+    /// along with its borrow site, like the wrapper's `mut_ref_debug` does.
+    pub fn debug_keys(mut self) -> Self
+        where Q: fmt::Debug
+    {
+        self.key_fmt = Some(<Q as fmt::Debug>::fmt);
+        self
+    }
+
+    /// Collects the next `N` references into an array. Returns `None` if the iterator runs out before that.
+    /// The keys are still looked up one at a time, so this has the same limitation under Miri as `next`.
+    #[track_caller]
+    pub fn collect_array<const N: usize>(mut self) -> Option<[&'a mut V; N]> {
+        let mut items = [(); N].map(|_| None);
+        for item in &mut items {    // Not array::map, since #[track_caller] doesn't reach through closures.
+            *item = self.next();
+        }
+        if items.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(items.map(Option::unwrap))
+    }
+}
+
+/// The iterator returned by `iter_multi_mut_entries`. Miri only accepts it under Tree Borrows, for the same reason as
+/// `BTreeMapMutWrapper`: each key is looked up after the references to the earlier values have been handed out.
+pub struct BTreeMapMultiMutEntries<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    mut_wrapper: BTreeMapMutWrapper<'a, K, V>,
+    keys: I,
+    key_fmt: Option<fn(&Q, &mut fmt::Formatter) -> fmt::Result>,
+    key_type: PhantomData<fn(&Q)>,
+}
+
+impl<'a, Q: ?Sized, I, K, V> Iterator for BTreeMapMultiMutEntries<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    type Item = (&'a K, &'a mut V);
+
+    /// Records the same borrow site as [`BTreeMapMultiMutIter::next`].
+    #[track_caller]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.mut_wrapper.used == self.mut_wrapper.buffer.len() { return None };
+        match self.keys.next() {
+            Some(q) => {
+                let q: &Q = q.borrow();
+                let index = self.mut_wrapper.used;
+                let result = self.mut_wrapper.try_get_key_value_mut(q, index);
+                Some(self.mut_wrapper.audit.settle(result, q, self.key_fmt))
+            },
+            None => None,
+        }
+    }
+}
+
+impl<'a, Q: ?Sized, I, K, V> BTreeMapMultiMutEntries<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    /// Includes the key in the panic messages, and records it along with the borrow site. See [`BTreeMapMultiMutIter::debug_keys`].
+    pub fn debug_keys(mut self) -> Self
+        where Q: fmt::Debug
+    {
+        self.key_fmt = Some(<Q as fmt::Debug>::fmt);
+        self
+    }
+}
+
+/// The iterator returned by `try_iter_multi_mut`. See there for how Miri treats it.
+pub struct BTreeMapMultiMutTryIter<'a, Q: ?Sized, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    mut_wrapper: BTreeMapMutWrapper<'a, K, V>,
+    keys: Enumerate<I>,
+    handed_out: Vec<I::Item>,
+    key_type: PhantomData<fn(&Q)>,
+}
+
+impl<'a, Q: ?Sized, I, K, V> Iterator for BTreeMapMultiMutTryIter<'a, Q, I, K, V>
+        where K: Borrow<Q> + Ord, Q: Ord, I: Iterator, I::Item: Borrow<Q>
+{
+    type Item = Result<&'a mut V, MultiMutError>;
+
+    #[track_caller]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, q) = self.keys.next()?;
+        // Looking up a key whose value was already handed out would invalidate the reference to it,
+        // so a repeated key is recognised by comparing it with the earlier keys instead.
+        if self.handed_out.iter().any(|earlier| Borrow::<Q>::borrow(earlier) == q.borrow()) {
+            return Some(Err(MultiMutError::Aliased { index }));
+        }
+        match self.mut_wrapper.try_get_mut(q.borrow(), index) {
+            Ok(v) => {
+                self.handed_out.push(q);
+                Some(Ok(v))
+            },
+            Err(e @ MultiMutError::Aliased { .. }) => self.mut_wrapper.audit.fail(e),   // Different keys led to the same value,
+                                                                                        // so the Borrow impl of the key is broken.
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/* Grids */
+
+/// Endows two-dimensional grids, `Vec<Vec<T>>` and `FlatGrid`, with extension methods that help getting multiple mutable
+/// references to their cells. Runtime-checking is done to ensure that this is safe: the returned mutable references are guaranteed not to alias.
+pub trait GridMultiMut {
+    type Cell;
+
+    /// Returns the cells at the `(row, column)` positions. Positions outside of the grid are reported
+    /// as `MultiMutError::NoSuchKey` and repeated positions as `MultiMutError::Aliased`.
+    fn get_cells_mut<const N: usize>(&mut self, cells: [(usize, usize); N]) -> Result<[&mut Self::Cell; N], MultiMutError>;
+
+    /// Returns the rows with the indices `rows`. Rows outside of the grid are reported
+    /// as `MultiMutError::NoSuchKey` and repeated rows as `MultiMutError::Aliased`.
+    fn get_rows_mut<const N: usize>(&mut self, rows: [usize; N]) -> Result<[&mut [Self::Cell]; N], MultiMutError>;
+
+    /// Returns the cell at `(row, col)` along with its eight neighbors, row by row from the top left one.
+    /// Neighbors outside of the grid are returned as `None`. Returns `None` if the cell itself is outside of the grid.
+    #[allow(clippy::type_complexity)]
+    fn get_with_neighbors_mut(&mut self, row: usize, col: usize) -> Option<(&mut Self::Cell, [Option<&mut Self::Cell>; 8])>;
+}
+
+/// A flat, row-major buffer viewed as a grid with rows of `width` cells. A trailing partial row is not part of the grid.
+#[derive(Debug)]
+pub struct FlatGrid<'a, T: 'a> {
+    cells: &'a mut [T],
+    width: usize,
+}
+
+impl<'a, T> FlatGrid<'a, T> {
+
+    pub fn new(cells: &'a mut [T], width: usize) -> Self {
+        FlatGrid { cells, width }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.cells.len().checked_div(self.width).unwrap_or(0)
+    }
+}
+
+/// The positions of the neighbors of a cell relative to it, row by row.
+const NEIGHBOR_OFFSETS: [(isize, isize); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+/// Checks that all the cells or rows exist and that no position is repeated. Positions are compared instead of pointers,
+/// because the pointers to zero-sized cells are all equal.
+fn check_positions<P: PartialEq, R>(positions: &[P], ptrs: &[Option<R>]) -> Result<(), MultiMutError> {
+    for (index, ptr) in ptrs.iter().enumerate() {
+        if ptr.is_none() {
+            return Err(MultiMutError::NoSuchKey { index });
+        }
+        if positions[..index].contains(&positions[index]) {
+            return Err(MultiMutError::Aliased { index });
+        }
+    }
+    Ok(())
+}
+
+/// Collects the cell at `(row, col)` and its neighbors. The caller must make sure that `cell_ptr` returns valid pointers,
+/// distinct for distinct positions, and that nothing else accesses the cells for `'a`.
+unsafe fn collect_neighborhood<'a, T, F>(row: usize, col: usize, mut cell_ptr: F) -> Option<(&'a mut T, [Option<&'a mut T>; 8])>
+    where F: FnMut(usize, usize) -> Option<*mut T>
+{
+    let center = cell_ptr(row, col)?;
+    let neighbors = NEIGHBOR_OFFSETS.map(|(row_offset, col_offset)| {
+        let ptr = cell_ptr(row.checked_add_signed(row_offset)?, col.checked_add_signed(col_offset)?)?;
+        Some(&mut *ptr)
+    });
+    Some((&mut *center, neighbors))
+}
+
+/// Looks up a pointer to a cell without creating references to the other cells of the row.
+fn vec_cell_ptr<T>(grid: &mut [Vec<T>], row: usize, col: usize) -> Option<*mut T> {
+    let cells = grid.get_mut(row)?;
+    if col < cells.len() {
+        unsafe { Some(cells.as_mut_ptr().add(col)) }
+    } else {
+        None
+    }
+}
+
+impl<T> GridMultiMut for Vec<Vec<T>> {
+    type Cell = T;
+
+    fn get_cells_mut<const N: usize>(&mut self, cells: [(usize, usize); N]) -> Result<[&mut T; N], MultiMutError> {
+        let ptrs = cells.map(|(row, col)| vec_cell_ptr(self, row, col));
+        check_positions(&cells, &ptrs)?;
+        unsafe { Ok(ptrs.map(|ptr| &mut *ptr.unwrap())) }  // This is safe to do because check_positions checked that the positions
+                                                            // are distinct, and vec_cell_ptr doesn't invalidate the earlier pointers.
+    }
+
+    fn get_rows_mut<const N: usize>(&mut self, rows: [usize; N]) -> Result<[&mut [T]; N], MultiMutError> {
+        let ptrs = rows.map(|row| self.get_mut(row).map(|cells| (cells.as_mut_ptr(), cells.len())));
+        check_positions(&rows, &ptrs)?;
+        unsafe { Ok(ptrs.map(|ptr| { let (ptr, len) = ptr.unwrap(); slice::from_raw_parts_mut(ptr, len) })) }
+            // This is safe to do because check_positions checked that the rows are distinct, and every row is
+            // a separate allocation. Looking up a row reborrows the Vec of that row, not its cells.
+    }
+
+    fn get_with_neighbors_mut(&mut self, row: usize, col: usize) -> Option<(&mut T, [Option<&mut T>; 8])> {
+        unsafe { collect_neighborhood(row, col, |row, col| vec_cell_ptr(self, row, col)) }  // vec_cell_ptr returns a distinct pointer
+                                                                                            // for every position, see get_cells_mut.
+    }
+}
+
+impl<'a, T> GridMultiMut for FlatGrid<'a, T> {
+    type Cell = T;
+
+    fn get_cells_mut<const N: usize>(&mut self, cells: [(usize, usize); N]) -> Result<[&mut T; N], MultiMutError> {
+        let (width, height, base) = (self.width, self.height(), self.cells.as_mut_ptr());
+        // The offsets are in bounds, because row < height and col < width imply row * width + col < self.cells.len().
+        let ptrs = cells.map(|(row, col)| if row < height && col < width { unsafe { Some(base.add(row * width + col)) } } else { None });
+        check_positions(&cells, &ptrs)?;
+        unsafe { Ok(ptrs.map(|ptr| &mut *ptr.unwrap())) }  // This is safe to do because check_positions checked that the positions
+                                                            // are distinct, and distinct positions in bounds are distinct cells.
+    }
+
+    fn get_rows_mut<const N: usize>(&mut self, rows: [usize; N]) -> Result<[&mut [T]; N], MultiMutError> {
+        let (width, height, base) = (self.width, self.height(), self.cells.as_mut_ptr());
+        let ptrs = rows.map(|row| if row < height { unsafe { Some(base.add(row * width)) } } else { None });
+        check_positions(&rows, &ptrs)?;
+        unsafe { Ok(ptrs.map(|ptr| slice::from_raw_parts_mut(ptr.unwrap(), width))) }
+            // This is safe to do because check_positions checked that the rows are distinct, and the rows below
+            // height cover disjoint ranges of width cells inside of self.cells.
+    }
+
+    fn get_with_neighbors_mut(&mut self, row: usize, col: usize) -> Option<(&mut T, [Option<&mut T>; 8])> {
+        let (width, height, base) = (self.width, self.height(), self.cells.as_mut_ptr());
+        unsafe { collect_neighborhood(row, col, |row, col| if row < height && col < width { Some(base.add(row * width + col)) } else { None }) }
+            // Distinct positions in bounds give distinct cells, see get_cells_mut.
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests_hash {
+
+    use std::collections::HashMap;
+    use HashMapMultiMut;
+    use {MultiMutError, AliasError, DuplicatePolicy, PairOrSingle, TransactionError};
+    use std::ptr::null_mut;
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::panic;
+    use std::sync::Once;
+
+    fn populate_hashmap() -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        map.insert("key_one".into(), "value_one".into());
+        map.insert("key_two".into(), "value_two".into());
+        map.insert("key_three".into(), "value_three".into());
+        map.insert("key_four".into(), "value_four".into());
+        map.insert("key_five".into(), "value_five".into());
+        map.insert("key_six".into(), "value_six".into());
+        map
+    }
+
+    #[test]
+    fn test_pair_success() {
+        let mut map = populate_hashmap();
+        let (one, two): (&mut String, &mut String) = map.get_pair_mut("key_one", "key_two").unwrap();
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+    }
+
+    #[test]
+    fn test_pair_nonexistent_key() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_mut("key_one", "key_hundred"), None);
+    }
+
+    #[test]
+    fn test_pair_overlap() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_mut("key_one", "key_one"), None);
+    }
+
+    #[test]
+    fn test_pair_panic_success() {
+        let mut map = populate_hashmap();
+        let (one, two): (&mut String, &mut String) = map.pair_mut("key_one", "key_two");
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1)")]
+    fn test_pair_panic_nonexistent_key() {
+        let mut map = populate_hashmap();
+        map.pair_mut("key_one", "key_hundred");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_pair_panic_overlap() {
+        let mut map = populate_hashmap();
+        map.pair_mut("key_one", "key_one");
+    }
+
+    #[test]
+    fn test_triple_success() {
+        let mut map = populate_hashmap();
+        let (one, two, three): (&mut String, &mut String, &mut String) = map.get_triple_mut("key_one", "key_two", "key_three").unwrap();
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_triple_nonexistent_key() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_hundred", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_1() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_two", "key_one"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_2() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_two", "key_two", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_3() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_three", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_4() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_one", "key_one"), None);
+    }
+
+    #[test]
+    fn test_triple_panic_success() {
+        let mut map = populate_hashmap();
+        let (one, two, three): (&mut String, &mut String, &mut String) = map.triple_mut("key_one", "key_two", "key_three");
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1)")]
+    fn test_triple_panic_nonexistent_key() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_hundred", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 2 point to the same value.")]
+    fn test_triple_panic_overlap_1() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_two", "key_one");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_triple_panic_overlap_2() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_two", "key_two", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 1 and 2 point to the same value.")]
+    fn test_triple_panic_overlap_3() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_three", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_triple_panic_overlap_4() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_one", "key_one");
+    }
+
+    #[test]
+    fn test_multi_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let one = wrapper.get_mut("key_one").unwrap();
+        let two = wrapper.get_mut("key_two").unwrap();
+        let three = wrapper.get_mut("key_three").unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_ref_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let one = wrapper.mut_ref("key_one");
+        let two = wrapper.mut_ref("key_two");
+        let three = wrapper.mut_ref("key_three");
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_over_capacity() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let _one = wrapper.get_mut("key_one").unwrap();
+        let _two = wrapper.get_mut("key_two").unwrap();
+        let _three = wrapper.get_mut("key_three").unwrap();
+        let _four = wrapper.get_mut("key_four").unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let _one = wrapper.get_mut("key_one").unwrap();
+        let _two = wrapper.get_mut("key_two").unwrap();
+        let _three = wrapper.get_mut("key_one").unwrap();
+    }
+
+    #[test]
+    fn test_multi_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        assert_eq!(wrapper.get_mut("key_hundred"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_ref_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        wrapper.mut_ref("key_hundred");
+    }
+
+    #[test]
+    fn test_multi_iter_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let one = wrapper.next().unwrap();
+        let two = wrapper.next().unwrap();
+        let three = wrapper.next().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_iter_over_capacity() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+        let _three = wrapper.next().unwrap();
+
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_one"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+        let _three = wrapper.next().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_hundred"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        wrapper.next();
+    }
+
+    #[test]
+    fn test_multi_iter_owned_keys() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys: Vec<String> = vec!["key_one".into(), "key_two".into()];
+        let mut wrapper = map.iter_multi_mut_by::<str, _>(keys, &mut buffer);
+
+        let one = wrapper.next().unwrap();
+        let two = wrapper.next().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    fn test_multi_iter_type_name() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let wrapper: ::HashMapMultiMutIter<str, String, String> = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.count(), 2);
+    }
+
+    #[test]
+    fn test_multi_iter_lazy_keys() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let names = ["one", "two", "three"];
+        let wrapper = map.iter_multi_mut_by::<str, _>(names.iter().map(|n| format!("key_{}", n)), &mut buffer);
+
+        for value in wrapper {
+            value.push_str("_edited");
+        }
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_try_iter_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let mut wrapper = map.try_iter_multi_mut::<str, _>(keys, &mut buffer);
+
+        let one = wrapper.next().unwrap().unwrap();
+        let two = wrapper.next().unwrap().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    fn test_multi_try_iter_errors() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 2];
+        let keys = ["key_one", "key_hundred", "key_one", "key_two", "key_three"];
+        let results: Vec<_> = map.try_iter_multi_mut::<str, _>(keys, &mut buffer).collect();
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(*results[0].as_ref().unwrap(), "value_one");
+        assert_eq!(results[1], Err(MultiMutError::NoSuchKey { index: 1 }));
+        assert_eq!(results[2], Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(*results[3].as_ref().unwrap(), "value_two");
+        assert_eq!(results[4], Err(MultiMutError::BufferDepleted { index: 4 }));
+    }
+
+    #[test]
+    fn test_pair_key_value_success() {
+        let mut map = populate_hashmap();
+        let ((key_1, one), (key_2, two)) = map.get_pair_key_value_mut("key_one", "key_two").unwrap();
+
+        assert_eq!(key_1, "key_one");
+        assert_eq!(key_2, "key_two");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+    }
+
+    #[test]
+    fn test_pair_key_value_overlap() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_key_value_mut("key_one", "key_one"), None);
+        assert_eq!(map.get_pair_key_value_mut("key_one", "key_hundred"), None);
+    }
+
+    #[test]
+    fn test_multi_key_value_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        let (key, one) = wrapper.get_key_value_mut("key_one").unwrap();
+        assert_eq!(key, "key_one");
+        assert_eq!(one, "value_one");
+        assert_eq!(wrapper.get_key_value_mut("key_hundred"), None);
+    }
+
+    #[test]
+    fn test_multi_iter_entries_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let mut wrapper = map.iter_multi_mut_entries::<str, _>(keys, &mut buffer);
+
+        let (key_1, one) = wrapper.next().unwrap();
+        let (key_2, two) = wrapper.next().unwrap();
+
+        assert_eq!(key_1, "key_one");
+        assert_eq!(key_2, "key_two");
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_entries_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_one"];
+        let mut wrapper = map.iter_multi_mut_entries::<str, _>(keys, &mut buffer);
+
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+    }
+
+    #[test]
+    fn test_batch_coalesce() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one", "key_three", "key_two"];
+        let batch = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Coalesce).unwrap();
+
+        assert_eq!(batch.values.len(), 3);
+        assert_eq!(batch.slots, vec![0, 1, 0, 2, 1]);
+
+        let mut values = batch.values;
+        for &slot in &batch.slots {
+            values[slot].push('!');
+        }
+
+        assert_eq!(map["key_one"], "value_one!!");
+        assert_eq!(map["key_two"], "value_two!!");
+        assert_eq!(map["key_three"], "value_three!");
+    }
+
+    #[test]
+    fn test_batch_skip() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_one", "key_two"];
+        let batch = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Skip).unwrap();
+
+        assert_eq!(batch.values, vec!["value_one", "value_two"]);
+        assert!(batch.slots.is_empty());
+    }
+
+    #[test]
+    fn test_batch_error() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        assert_eq!(map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Error).unwrap_err(), MultiMutError::Aliased { index: 2 });
+
+        let keys = ["key_one", "key_hundred"];
+        assert_eq!(map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Coalesce).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_batch_panic() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        let _ = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Panic);
+    }
+
+    #[test]
+    fn test_multi_iter_size_hint() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three", "key_four"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.len(), 3);
+        wrapper.next();
+        assert_eq!(wrapper.size_hint(), (2, Some(2)));
+    }
+
+    #[test]
+    fn test_multi_iter_rev() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three", "key_four"];
+        let values: Vec<_> = map.iter_multi_mut(&keys, &mut buffer).rev().collect();
+
+        assert_eq!(values, vec!["value_three", "value_two", "value_one"]);
+    }
+
+    #[test]
+    fn test_multi_iter_both_ends() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 2];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.next_back().unwrap(), "value_two");
+        assert_eq!(wrapper.next().unwrap(), "value_one");
+        assert_eq!(wrapper.next(), None);
+        assert_eq!(wrapper.next_back(), None);
+    }
+
+    #[test]
+    fn test_multi_iter_collect_array() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let [one, two, three] = map.iter_multi_mut(&keys, &mut buffer).collect_array().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        assert_eq!(map.iter_multi_mut(&keys, &mut buffer).collect_array::<3>(), None);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_iter_success() {
+        use rayon::iter::ParallelIterator;
+
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_three"];
+        map.par_iter_multi_mut::<str, _>(keys).for_each(|v| v.push_str("_edited"));
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+        assert_eq!(map["key_four"], "value_four");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    #[should_panic]
+    fn test_par_iter_same_key() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        map.par_iter_multi_mut::<str, _>(keys);
+    }
+
+    #[test]
+    fn test_wrappers_are_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one"];
+        assert_send(&map.iter_multi_mut(&keys, &mut buffer));
+        assert_send(&map.multi_mut(&mut buffer));
+    }
+
+    #[test]
+    fn test_split_for_threads() {
+        let mut map = populate_hashmap();
+
+        {
+            let groups = map.split_for_threads::<str, _, _>(vec![vec!["key_one", "key_two"], vec!["key_three"]]);
+            assert_eq!(groups.len(), 2);
+
+            ::std::thread::scope(|scope| {
+                for group in groups {
+                    scope.spawn(move || {
+                        for value in group {
+                            value.push_str("_edited");
+                        }
+                    });
+                }
+            });
+        }
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+        assert_eq!(map["key_four"], "value_four");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_for_threads_overlap() {
+        let mut map = populate_hashmap();
+        map.split_for_threads::<str, _, _>(vec![vec!["key_one", "key_two"], vec!["key_one"]]);
+    }
+
+    fn populate_nested() -> HashMap<String, ::std::collections::BTreeMap<String, Vec<i32>>> {
+        let mut map = HashMap::new();
+        let mut tenant_one = ::std::collections::BTreeMap::new();
+        tenant_one.insert("account_one".to_string(), vec![1, 2]);
+        tenant_one.insert("account_two".to_string(), vec![3]);
+        let mut tenant_two = ::std::collections::BTreeMap::new();
+        tenant_two.insert("account_one".to_string(), vec![4]);
+        map.insert("tenant_one".to_string(), tenant_one);
+        map.insert("tenant_two".to_string(), tenant_two);
+        map
+    }
+
+    #[test]
+    fn test_paths_success() {
+        let mut map = populate_nested();
+
+        {
+            let [from, to, other] = map.get_paths_mut::<str, str, 3>([("tenant_one", "account_one"), ("tenant_two", "account_one"), ("tenant_one", "account_two")]).unwrap();
+            to.append(from);
+            other.push(5);
+        }
+
+        assert_eq!(map["tenant_one"]["account_one"], vec![]);
+        assert_eq!(map["tenant_one"]["account_two"], vec![3, 5]);
+        assert_eq!(map["tenant_two"]["account_one"], vec![4, 1, 2]);
+    }
+
+    #[test]
+    fn test_paths_errors() {
+        let mut map = populate_nested();
+
+        assert_eq!(map.get_paths_mut::<str, str, 2>([("tenant_one", "account_one"), ("tenant_one", "account_one")]).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(map.get_paths_mut::<str, str, 2>([("tenant_one", "account_one"), ("tenant_two", "account_two")]).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(map.get_paths_mut::<str, str, 1>([("tenant_three", "account_one")]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
+    }
+
+    #[test]
+    fn test_pair_entry_or_insert() {
+        let mut map = populate_hashmap();
+
+        {
+            let (one, new) = map.pair_entry_or_insert_with("key_one", "key_new", || "value_new".to_string());
+            assert_eq!(one, "value_one");
+            assert_eq!(new, "value_new");
+            new.push_str("_edited");
+        }
+
+        assert_eq!(map["key_new"], "value_new_edited");
+        assert_eq!(map.len(), 7);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pair_entry_or_insert_overlap() {
+        let mut map = populate_hashmap();
+        map.pair_entry_or_insert_with("key_new", "key_new", String::new);
+    }
+
+    #[test]
+    fn test_many_or_insert() {
+        let mut map = populate_hashmap();
+
+        {
+            let [one, new, other] = map.get_many_or_insert_with(["key_one", "key_new", "key_other"], String::new).unwrap();
+            assert_eq!(one, "value_one");
+            assert_eq!(new, "");
+            assert_eq!(other, "");
+            new.push_str("value_new");
+        }
+
+        assert_eq!(map["key_new"], "value_new");
+        assert_eq!(map.len(), 8);
+    }
+
+    #[test]
+    fn test_many_or_insert_overlap() {
+        let mut map = populate_hashmap();
+
+        assert_eq!(map.get_many_or_insert_with(["key_new", "key_one", "key_new"], String::new).unwrap_err(), MultiMutError::Aliased { index: 2 });
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn test_pair_or_single() {
+        let mut map = populate_hashmap();
+
+        match map.get_pair_or_single_mut("key_one", "key_two") {
+            Some(PairOrSingle::Pair(one, two)) => {
+                assert_eq!(one, "value_one");
+                assert_eq!(two, "value_two");
+            },
+            other => panic!("Expected a pair, got {:?}", other),
+        }
+
+        match map.get_pair_or_single_mut("key_one", "key_one") {
+            Some(PairOrSingle::Same(one)) => assert_eq!(one, "value_one"),
+            other => panic!("Expected a single value, got {:?}", other),
+        }
+
+        assert_eq!(map.get_pair_or_single_mut("key_one", "key_hundred"), None);
+    }
+
+    #[test]
+    fn test_swap_values() {
+        let mut map = populate_hashmap();
+
+        map.swap_values("key_one", "key_two").unwrap();
+        assert_eq!(map["key_one"], "value_two");
+        assert_eq!(map["key_two"], "value_one");
+
+        assert_eq!(map.swap_values("key_one", "key_one"), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.swap_values("key_hundred", "key_one"), Err(MultiMutError::NoSuchKey { index: 0 }));
+    }
+
+    #[test]
+    fn test_rotate_values() {
+        let mut map = populate_hashmap();
+
+        map.rotate_values(&["key_one", "key_two", "key_three"]).unwrap();
+        assert_eq!(map["key_one"], "value_three");
+        assert_eq!(map["key_two"], "value_one");
+        assert_eq!(map["key_three"], "value_two");
+
+        assert_eq!(map.rotate_values(&["key_one", "key_two", "key_one"]), Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(map["key_one"], "value_three");
+    }
+
+    #[test]
+    fn test_permute_values() {
+        let mut map = populate_hashmap();
+
+        map.permute_values(&["key_one", "key_two", "key_three", "key_four"], &[3, 2, 0, 1]).unwrap();
+        assert_eq!(map["key_one"], "value_four");
+        assert_eq!(map["key_two"], "value_three");
+        assert_eq!(map["key_three"], "value_one");
+        assert_eq!(map["key_four"], "value_two");
+
+        assert_eq!(map.permute_values(&["key_one", "key_hundred"], &[1, 0]), Err(MultiMutError::NoSuchKey { index: 1 }));
+    }
+
+    #[test]
+    fn test_permute_values_invalid() {
+        let mut map = populate_hashmap();
+
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[1, 1]), Err(MultiMutError::InvalidPermutation { index: 1 }));
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[2, 0]), Err(MultiMutError::InvalidPermutation { index: 0 }));
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[1]), Err(MultiMutError::InvalidPermutation { index: 1 }));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
+    }
+
+    #[test]
+    fn test_merge_into() {
+        let mut map = populate_hashmap();
+
+        let len = map.merge_into("key_two", "key_one", |dst, src| { dst.push_str(&src); dst.len() }).unwrap();
+
+        assert_eq!(len, 18);
+        assert_eq!(map["key_one"], "value_onevalue_two");
+        assert!(!map.contains_key("key_two"));
+    }
+
+    #[test]
+    fn test_merge_into_errors() {
+        let mut map = populate_hashmap();
+
+        assert_eq!(map.merge_into("key_one", "key_one", |_, _| ()), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.merge_into("key_hundred", "key_one", |_, _| ()), Err(MultiMutError::NoSuchKey { index: 0 }));
+        assert_eq!(map.merge_into("key_one", "key_hundred", |_, _| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn test_remove_many() {
+        let mut map = populate_hashmap();
+
+        let [(key_1, one), (key_2, two)] = map.remove_many(["key_one", "key_two"]).unwrap();
+
+        assert_eq!((key_1.as_str(), one.as_str()), ("key_one", "value_one"));
+        assert_eq!((key_2.as_str(), two.as_str()), ("key_two", "value_two"));
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn test_remove_many_errors() {
+        let mut map = populate_hashmap();
+
+        assert_eq!(map.remove_many(["key_one", "key_two", "key_one"]).unwrap_err(), MultiMutError::Aliased { index: 2 });
+        assert_eq!(map.remove_many(["key_one", "key_hundred"]).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let mut map = populate_hashmap();
+
+        let result: Result<usize, TransactionError<()>> = map.transaction(["key_one", "key_two"], |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Ok(one.len())
+        });
+
+        assert_eq!(result, Ok(16));
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let mut map = populate_hashmap();
+
+        let result: Result<(), _> = map.transaction(["key_one", "key_two"], |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Err("insufficient funds")
+        });
+
+        assert_eq!(result, Err(TransactionError::Aborted("insufficient funds")));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
+    }
+
+    #[test]
+    fn test_transaction_rollback_on_panic() {
+        let mut map = populate_hashmap();
+
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            let _: Result<(), TransactionError<()>> = map.transaction(["key_one", "key_two"], |[one, _two]| {
+                one.push_str("_edited");
+                panic!("Something went wrong!");
+            });
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(map["key_one"], "value_one");
+    }
+
+    #[test]
+    fn test_transaction_with_undo() {
+        let mut map = populate_hashmap();
+
+        let result: Result<(), _> = map.transaction_with(["key_one", "key_two"], String::len, |v, len| v.truncate(len), |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Err(())
+        });
+
+        assert_eq!(result, Err(TransactionError::Aborted(())));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
+
+        let result: Result<(), TransactionError<()>> = map.transaction(["key_one", "key_one"], |_| Ok(()));
+        assert_eq!(result, Err(TransactionError::Keys(MultiMutError::Aliased { index: 1 })));
+    }
+
+    #[test]
+    fn test_with_pair_and_triple() {
+        let mut map = populate_hashmap();
+
+        let len = map.with_pair_mut("key_one", "key_two", |one, two| { one.push_str(two); one.len() }).unwrap();
+        assert_eq!(len, 18);
+        assert_eq!(map["key_one"], "value_onevalue_two");
+
+        map.with_triple_mut("key_one", "key_two", "key_three", |one, two, three| {
+            ::std::mem::swap(one, two);
+            three.clear();
+        }).unwrap();
+        assert_eq!(map["key_one"], "value_two");
+        assert_eq!(map["key_three"], "");
+
+        assert_eq!(map.with_pair_mut("key_one", "key_one", |_, _| ()), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.with_triple_mut("key_one", "key_two", "key_hundred", |_, _, _| ()), Err(MultiMutError::NoSuchKey { index: 2 }));
+    }
+
+    #[test]
+    fn test_with_many() {
+        let mut map = populate_hashmap();
+
+        let total = map.with_many_mut(["key_one", "key_two", "key_three", "key_four"], |values| {
+            values.iter().map(|v| v.len()).sum::<usize>()
+        }).unwrap();
+        assert_eq!(total, 9 + 9 + 11 + 10);
+
+        assert_eq!(map.with_many_mut(["key_one", "key_two", "key_one"], |_| ()), Err(MultiMutError::Aliased { index: 2 }));
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let mut tree: HashMap<u32, (Option<u32>, u32)> = HashMap::new();
+        tree.insert(1, (None, 0));
+        tree.insert(2, (Some(1), 0));
+        tree.insert(3, (Some(2), 0));
+        tree.insert(4, (Some(1), 0));
+
+        {
+            let chain = tree.follow_chain_mut(&3, |node| node.0).unwrap();
+            assert_eq!(chain.len(), 3);
+            for node in chain {
+                node.1 += 1;
+            }
+        }
+
+        assert_eq!(tree[&1].1, 1);
+        assert_eq!(tree[&2].1, 1);
+        assert_eq!(tree[&3].1, 1);
+        assert_eq!(tree[&4].1, 0);
+    }
+
+    #[test]
+    fn test_follow_chain_errors() {
+        let mut tree: HashMap<u32, Option<u32>> = HashMap::new();
+        tree.insert(1, Some(3));
+        tree.insert(2, Some(1));
+        tree.insert(3, Some(2));
+        tree.insert(4, Some(5));
+        tree.insert(6, Some(6));
+
+        assert_eq!(tree.follow_chain_mut(&1, |link| *link).unwrap_err(), MultiMutError::Aliased { index: 3 });
+        assert_eq!(tree.follow_chain_mut(&4, |link| *link).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(tree.follow_chain_mut(&6, |link| *link).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(tree.follow_chain_mut(&7, |link| *link).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
+    }
+
+    #[test]
+    fn test_with_neighbors() {
+        let mut grid: HashMap<(i32, i32), u32> = HashMap::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                grid.insert((x, y), 1);
+            }
+        }
+
+        {
+            let (x, y) = (0, 1);
+            let (center, neighbors) = grid.get_with_neighbors_mut(&(x, y), vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]).unwrap();
+            assert_eq!(neighbors.len(), 4);
+            assert!(neighbors[0].is_none());
+            for neighbor in neighbors.into_iter().flatten() {
+                *center += *neighbor;
+                *neighbor = 0;
+            }
+        }
+
+        assert_eq!(grid[&(0, 1)], 4);
+        assert_eq!(grid[&(1, 1)], 0);
+        assert_eq!(grid[&(0, 0)], 0);
+        assert_eq!(grid[&(1, 0)], 1);
+    }
+
+    #[test]
+    fn test_with_neighbors_overlap() {
+        let mut map = populate_hashmap();
+
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_hundred"]).is_some());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_one"]).is_none());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_two"]).is_none());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_hundred", ["key_two"]).is_none());
+    }
+
+    #[test]
+    fn test_many_opt() {
+        let mut map = populate_hashmap();
+
+        let [one, missing, three] = map.get_many_mut_opt(["key_one", "key_hundred", "key_three"]).unwrap();
+
+        assert_eq!(one.unwrap(), "value_one");
+        assert_eq!(missing, None);
+        assert_eq!(three.unwrap(), "value_three");
+    }
+
+    #[test]
+    fn test_many_opt_overlap() {
+        let mut map = populate_hashmap();
+
+        assert_eq!(map.get_many_mut_opt(["key_hundred", "key_hundred", "key_one"]).map(|_| ()), Ok(()));
+        assert_eq!(map.get_many_mut_opt(["key_one", "key_hundred", "key_one"]).unwrap_err(), AliasError { first: 0, index: 2 });
+        assert_eq!(MultiMutError::from(AliasError { first: 0, index: 2 }), MultiMutError::Aliased { index: 2 });
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_many_vec() {
+        let mut map = populate_hashmap();
+
+        let keys: Vec<&str> = "key_one key_two key_three".split(' ').collect();
+        let values = map.get_many_mut_vec(&keys).unwrap();
+        assert_eq!(values, vec!["value_one", "value_two", "value_three"]);
+
+        assert_eq!(map.get_many_mut_vec(&["key_one", "key_one"]).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(map.get_many_mut_vec(&["key_hundred"]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
+    }
+
+    #[test]
+    fn test_tuple() {
+        let mut map = populate_hashmap();
+
+        let two = String::from("key_two");
+        let (one, two, three) = map.get_tuple_mut(("key_one", &two, "key_three")).unwrap();
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+        ::std::mem::swap(one, three);
+        assert_eq!(map["key_one"], "value_three");
+
+        assert_eq!(map.get_tuple_mut(("key_one", "key_two", &String::from("key_one"))).map(|_| ()), Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(map.get_tuple_mut(("key_one", "key_hundred")).map(|_| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
+    }
+
+    #[test]
+    fn test_macro() {
+        let mut map = populate_hashmap();
+
+        let two = String::from("key_two");
+        let (one, two, three) = multi_mut!(map, "key_one", &two, "key_three");
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        ::std::mem::swap(one, three);
+        assert_eq!(map["key_three"], "value_one");
+
+        let (four,) = multi_mut!(map, "key_four",);
+        assert_eq!(four, "value_four");
+    }
+
+    #[test]
+    fn test_macro_integer_literals() {
+        let mut map: HashMap<u64, i32> = (0..4).map(|i| (i, 0)).collect();
+
+        let (a, b, c) = multi_mut!(map, 1, 0x2, 3);
+        *a += 1;
+        *b += 2;
+        *c += 3;
+        assert_eq!(map[&1] + map[&2] + map[&3], 6);
+
+        let mut map: HashMap<Vec<u8>, i32> = vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2)].into_iter().collect();
+        let (a, b) = multi_mut!(map, b"a", b"b");
+        ::std::mem::swap(a, b);
+        assert_eq!(map[&b"a"[..]], 2);
+    }
+
+    #[test]
+    fn test_macro_arity() {
+        let mut map: HashMap<i32, i32> = (0..20).map(|i| (i, i)).collect();
+
+        let keys: Vec<i32> = (0..13).collect();
+        let (a, _, _, _, _, _, _, _, _, _, _, _, m) = multi_mut!(map, &keys[0], &keys[1], &keys[2], &keys[3], &keys[4],
+            &keys[5], &keys[6], &keys[7], &keys[8], &keys[9], &keys[10], &keys[11], &keys[12]);
+        *a += 100;
+        *m += 100;
+        assert_eq!(map[&0], 100);
+        assert_eq!(map[&12], 112);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_macro_overlap() {
+        let mut map = populate_hashmap();
+
+        let one = String::from("key_one");
+        let (_, _) = multi_mut!(map, "key_one", &one);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_macro_no_such_key() {
+        let mut map = populate_hashmap();
+
+        let (_, _) = multi_mut!(map, "key_one", "key_hundred");
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_named() {
+        use MultiMutKeys;
+
+        #[derive(MultiMutKeys)]
+        struct Transfer<'k> {
+            src: &'k str,
+            dst: String,
+            fee: &'k str,
+        }
+
+        let mut map = populate_hashmap();
+
+        let keys = Transfer { src: "key_one", dst: String::from("key_two"), fee: "key_three" };
+        let TransferMut { src, dst, fee } = map.get_named_mut(&keys).unwrap();
+        assert_eq!(fee, "value_three");
+        ::std::mem::swap(src, dst);
+        assert_eq!(map["key_one"], "value_two");
+
+        let keys = Transfer { src: "key_one", dst: String::from("key_hundred"), fee: "key_three" };
+        assert_eq!(map.get_named_mut(&keys).map(|_| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
+        let keys = Transfer { src: "key_one", dst: String::from("key_two"), fee: "key_one" };
+        assert_eq!(map.get_named_mut(&keys).map(|_| ()), Err(MultiMutError::Aliased { index: 2 }));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_named_generic_defaults() {
+        use MultiMutKeys;
+
+        #[derive(MultiMutKeys)]
+        struct Pair<T = String, const N: usize = 2> {
+            first: T,
+            second: T,
+        }
+
+        let mut map = populate_hashmap();
+
+        let keys: Pair = Pair { first: "key_one".into(), second: "key_two".into() };
+        let PairMut { first, second } = map.get_named_mut(&keys).unwrap();
+        ::std::mem::swap(first, second);
+        assert_eq!(map["key_one"], "value_two");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_macro_panic_names_key() {
+        let mut map = populate_hashmap();
+
+        let (_, _, _) = multi_mut!(map, "key_one", "key_hundred", "key_two");
+    }
+
+    #[test]
+    #[should_panic(expected = "The key at index 2 has been already used. The key was \"key_one\".")]
+    fn test_macro_panic_names_aliased_key() {
+        let mut map = populate_hashmap();
+
+        let one = String::from("key_one");
+        let (_, _, _) = multi_mut!(map, "key_one", "key_two", &one);
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 0)")]
+    fn test_macro_panic_without_debug() {
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct Opaque(u32);
+
+        let mut map = HashMap::new();
+        map.insert(Opaque(1), 1);
+        let (_, _) = multi_mut!(map, &Opaque(2), &Opaque(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 2)")]
+    fn test_mut_ref_panic_index() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        wrapper.mut_ref("key_one");
+        wrapper.mut_ref("key_two");
+        wrapper.mut_ref("key_hundred");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_pair_debug_panic_names_key() {
+        let mut map = populate_hashmap();
+        map.pair_mut_debug("key_one", "key_hundred");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 2 point to the same value. The key was \"key_one\".")]
+    fn test_triple_debug_panic_names_key() {
+        let mut map = populate_hashmap();
+        map.triple_mut_debug("key_one", "key_two", "key_one");
+    }
+
+    #[test]
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_one\".")]
+    fn test_mut_ref_debug_panic_names_key() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        wrapper.mut_ref_debug("key_one");
+        wrapper.mut_ref_debug("key_one");
+    }
+
+    /// Runs `f`, which is expected to panic, and returns the file and line the panic was reported at,
+    /// along with the payload to resume the panic with.
+    fn panic_location<F: FnOnce()>(f: F) -> ((String, u32), Box<dyn Any + Send>) {
+        thread_local!(static LOCATION: RefCell<Option<(String, u32)>> = const { RefCell::new(None) });
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if let Some(location) = info.location() {
+                    LOCATION.with(|l| *l.borrow_mut() = Some((location.file().to_string(), location.line())));
+                }
+                default_hook(info);
+            }));
+        });
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_err();
+        (LOCATION.with(|l| l.borrow_mut().take()).unwrap(), payload)
+    }
+
+    #[test]
+    #[should_panic(expected = "No aliased references allowed! The keys at indices 0 and 2 point to the same value.")]
+    fn test_batch_panic_location() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        let (location, payload) = panic_location(|| { let _ = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Panic); });
+        assert_eq!(location, (file!().to_string(), line!() - 1));
+        panic::resume_unwind(payload);
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_iter_debug_keys_panic_names_key() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_hundred"];
+
+        for _ in map.iter_multi_mut(&keys, &mut buffer).debug_keys() {}
+    }
+
+    #[test]
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_two\".")]
+    fn test_entries_debug_keys_panic_names_key() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+
+        for _ in map.iter_multi_mut_entries::<str, _>(["key_two", "key_two"], &mut buffer).debug_keys() {}
+    }
+
+    #[test]
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_one\".")]
+    fn test_get_key_value_mut_debug_panic_names_key() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        assert_eq!(wrapper.get_key_value_mut_debug("key_hundred"), None);
+        wrapper.get_key_value_mut_debug("key_one").unwrap();
+        wrapper.get_key_value_mut_debug("key_one");
+    }
+
+    #[test]
+    fn test_wrapper_debug() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        wrapper.get_mut("key_one").unwrap();
+        wrapper.get_mut_debug("key_two").unwrap();
+        assert!(format!("{:?}", wrapper).starts_with("HashMapMutWrapper { used: 2, capacity: 3"));
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_audit() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        let line = line!() + 1;
+        wrapper.get_mut_debug("key_one").unwrap();
+        wrapper.get_mut("key_two").unwrap();
+        assert_eq!(wrapper.get_mut_debug("key_hundred"), None);
+
+        let sites = wrapper.borrow_sites().to_vec();
+        assert_eq!(sites.len(), 2);
+        assert_eq!((sites[0].location.file(), sites[0].location.line()), (file!(), line));
+        assert_eq!(sites[1].location.line(), line + 1);
+        assert_eq!(sites[0].key, Some("\"key_one\"".to_string()));
+        assert_eq!(sites[1].key, None);
+        let first = format!("\"key_one\" at {}:{}:", file!(), line);
+        assert!(format!("{:?}", wrapper).contains(&format!("borrows: [{}", first)));
+
+        let payload = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| { wrapper.mut_ref("key_one"); })).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(message.contains(&format!("First borrowed: {}", first)), "{}", message);
+        assert!(message.contains(&format!("and again at {}:{}:", file!(), line!() - 3)), "{}", message);
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_audit_iter_keys() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+
+        // Calling next() directly records the line of the call, unlike a for loop, which records a line inside std.
+        let mut iter = map.iter_multi_mut(&keys, &mut buffer).debug_keys();
+        let line = line!() + 1;
+        iter.next().unwrap();
+        iter.next().unwrap();
+        let sites = iter.mut_wrapper.borrow_sites().to_vec();
+        assert_eq!(sites.iter().map(|site| site.key.clone()).collect::<Vec<_>>(),
+                   vec![Some("\"key_one\"".to_string()), Some("\"key_two\"".to_string())]);
+        assert_eq!((sites[0].location.file(), sites[0].location.line()), (file!(), line));
+        assert_eq!(sites[1].location.line(), line + 1);
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        wrapper.get_key_value_mut_debug("key_three").unwrap();
+        wrapper.mut_ref_debug("key_one");
+        let sites = wrapper.borrow_sites();
+        assert_eq!(sites[0].key, Some("\"key_three\"".to_string()));
+        assert_eq!(sites[1].key, Some("\"key_one\"".to_string()));
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests_btree {
+
+    use std::collections::BTreeMap;
+    use BTreeMapMultiMut;
+    use {MultiMutError, AliasError, DuplicatePolicy, PairOrSingle, TransactionError};
+    use std::ptr::null_mut;
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::panic;
+    use std::sync::Once;
+
+    fn populate_hashmap() -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("key_one".into(), "value_one".into());
+        map.insert("key_two".into(), "value_two".into());
+        map.insert("key_three".into(), "value_three".into());
+        map.insert("key_four".into(), "value_four".into());
+        map.insert("key_five".into(), "value_five".into());
+        map.insert("key_six".into(), "value_six".into());
+        map
+    }
+
+    #[test]
+    fn test_pair_success() {
+        let mut map = populate_hashmap();
+        let (one, two): (&mut String, &mut String) = map.get_pair_mut("key_one", "key_two").unwrap();
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+    }
+
+    #[test]
+    fn test_pair_nonexistent_key() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_mut("key_one", "key_hundred"), None);
+    }
+
+    #[test]
+    fn test_pair_overlap() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_mut("key_one", "key_one"), None);
+    }
+
+    #[test]
+    fn test_pair_panic_success() {
+        let mut map = populate_hashmap();
+        let (one, two): (&mut String, &mut String) = map.pair_mut("key_one", "key_two");
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1)")]
+    fn test_pair_panic_nonexistent_key() {
+        let mut map = populate_hashmap();
+        map.pair_mut("key_one", "key_hundred");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_pair_panic_overlap() {
+        let mut map = populate_hashmap();
+        map.pair_mut("key_one", "key_one");
+    }
+
+    #[test]
+    fn test_triple_success() {
+        let mut map = populate_hashmap();
+        let (one, two, three): (&mut String, &mut String, &mut String) = map.get_triple_mut("key_one", "key_two", "key_three").unwrap();
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_triple_nonexistent_key() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_hundred", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_1() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_two", "key_one"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_2() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_two", "key_two", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_3() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_three", "key_three"), None);
+    }
+
+    #[test]
+    fn test_triple_overlap_4() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_triple_mut("key_one", "key_one", "key_one"), None);
+    }
+
+    #[test]
+    fn test_triple_panic_success() {
+        let mut map = populate_hashmap();
+        let (one, two, three): (&mut String, &mut String, &mut String) = map.triple_mut("key_one", "key_two", "key_three");
+        
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1)")]
+    fn test_triple_panic_nonexistent_key() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_hundred", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 2 point to the same value.")]
+    fn test_triple_panic_overlap_1() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_two", "key_one");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_triple_panic_overlap_2() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_two", "key_two", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 1 and 2 point to the same value.")]
+    fn test_triple_panic_overlap_3() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_three", "key_three");
+    }
+
+    #[test]
+    #[should_panic(expected = "The keys at indices 0 and 1 point to the same value.")]
+    fn test_triple_panic_overlap_4() {
+        let mut map = populate_hashmap();
+        map.triple_mut("key_one", "key_one", "key_one");
+    }
+
+    #[test]
+    fn test_multi_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let one = wrapper.get_mut("key_one").unwrap();
+        let two = wrapper.get_mut("key_two").unwrap();
+        let three = wrapper.get_mut("key_three").unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_ref_success() {
         let mut map = populate_hashmap();
-        let (one, two): (&mut String, &mut String) = map.get_pair_mut("key_one", "key_two").unwrap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let one = wrapper.mut_ref("key_one");
+        let two = wrapper.mut_ref("key_two");
+        let three = wrapper.mut_ref("key_three");
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_over_capacity() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let _one = wrapper.get_mut("key_one").unwrap();
+        let _two = wrapper.get_mut("key_two").unwrap();
+        let _three = wrapper.get_mut("key_three").unwrap();
+        let _four = wrapper.get_mut("key_four").unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        let _one = wrapper.get_mut("key_one").unwrap();
+        let _two = wrapper.get_mut("key_two").unwrap();
+        let _three = wrapper.get_mut("key_one").unwrap();
+    }
+
+    #[test]
+    fn test_multi_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        assert_eq!(wrapper.get_mut("key_hundred"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_ref_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+        
+        wrapper.mut_ref("key_hundred");
+    }
+
+    #[test]
+    fn test_multi_iter_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let one = wrapper.next().unwrap();
+        let two = wrapper.next().unwrap();
+        let three = wrapper.next().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
+
+        one.push_str("_edited");
+        two.push_str("_edited");
+        three.push_str("_edited");
+
+        assert_eq!(one, "value_one_edited");
+        assert_eq!(two, "value_two_edited");
+        assert_eq!(three, "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_iter_over_capacity() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+        let _three = wrapper.next().unwrap();
+
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_one"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+        
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+        let _three = wrapper.next().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_nonexistent() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_hundred"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
         
+        wrapper.next();
+    }
+
+    #[test]
+    fn test_multi_iter_owned_keys() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys: Vec<String> = vec!["key_one".into(), "key_two".into()];
+        let mut wrapper = map.iter_multi_mut_by::<str, _>(keys, &mut buffer);
+
+        let one = wrapper.next().unwrap();
+        let two = wrapper.next().unwrap();
+
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    fn test_multi_iter_type_name() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let wrapper: ::BTreeMapMultiMutIter<str, String, String> = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.count(), 2);
+    }
+
+    #[test]
+    fn test_multi_iter_lazy_keys() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let names = ["one", "two", "three"];
+        let wrapper = map.iter_multi_mut_by::<str, _>(names.iter().map(|n| format!("key_{}", n)), &mut buffer);
+
+        for value in wrapper {
+            value.push_str("_edited");
+        }
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+    }
+
+    #[test]
+    fn test_multi_try_iter_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let mut wrapper = map.try_iter_multi_mut::<str, _>(keys, &mut buffer);
+
+        let one = wrapper.next().unwrap().unwrap();
+        let two = wrapper.next().unwrap().unwrap();
+
         assert_eq!(one, "value_one");
         assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    fn test_multi_try_iter_errors() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 2];
+        let keys = ["key_one", "key_hundred", "key_one", "key_two", "key_three"];
+        let results: Vec<_> = map.try_iter_multi_mut::<str, _>(keys, &mut buffer).collect();
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(*results[0].as_ref().unwrap(), "value_one");
+        assert_eq!(results[1], Err(MultiMutError::NoSuchKey { index: 1 }));
+        assert_eq!(results[2], Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(*results[3].as_ref().unwrap(), "value_two");
+        assert_eq!(results[4], Err(MultiMutError::BufferDepleted { index: 4 }));
+    }
+
+    #[test]
+    fn test_pair_key_value_success() {
+        let mut map = populate_hashmap();
+        let ((key_1, one), (key_2, two)) = map.get_pair_key_value_mut("key_one", "key_two").unwrap();
+
+        assert_eq!(key_1, "key_one");
+        assert_eq!(key_2, "key_two");
 
         one.push_str("_edited");
         two.push_str("_edited");
@@ -448,649 +4571,1019 @@ mod tests_hash {
     }
 
     #[test]
-    fn test_pair_nonexistent_key() {
+    fn test_pair_key_value_overlap() {
+        let mut map = populate_hashmap();
+        assert_eq!(map.get_pair_key_value_mut("key_one", "key_one"), None);
+        assert_eq!(map.get_pair_key_value_mut("key_one", "key_hundred"), None);
+    }
+
+    #[test]
+    fn test_multi_key_value_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        let (key, one) = wrapper.get_key_value_mut("key_one").unwrap();
+        assert_eq!(key, "key_one");
+        assert_eq!(one, "value_one");
+        assert_eq!(wrapper.get_key_value_mut("key_hundred"), None);
+    }
+
+    #[test]
+    fn test_multi_iter_entries_success() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        let mut wrapper = map.iter_multi_mut_entries::<str, _>(keys, &mut buffer);
+
+        let (key_1, one) = wrapper.next().unwrap();
+        let (key_2, two) = wrapper.next().unwrap();
+
+        assert_eq!(key_1, "key_one");
+        assert_eq!(key_2, "key_two");
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        assert_eq!(wrapper.next(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multi_iter_entries_same_key() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_one"];
+        let mut wrapper = map.iter_multi_mut_entries::<str, _>(keys, &mut buffer);
+
+        let _one = wrapper.next().unwrap();
+        let _two = wrapper.next().unwrap();
+    }
+
+    #[test]
+    fn test_batch_coalesce() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one", "key_three", "key_two"];
+        let batch = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Coalesce).unwrap();
+
+        assert_eq!(batch.values.len(), 3);
+        assert_eq!(batch.slots, vec![0, 1, 0, 2, 1]);
+
+        let mut values = batch.values;
+        for &slot in &batch.slots {
+            values[slot].push('!');
+        }
+
+        assert_eq!(map["key_one"], "value_one!!");
+        assert_eq!(map["key_two"], "value_two!!");
+        assert_eq!(map["key_three"], "value_three!");
+    }
+
+    #[test]
+    fn test_batch_skip() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_one", "key_two"];
+        let batch = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Skip).unwrap();
+
+        assert_eq!(batch.values, vec!["value_one", "value_two"]);
+        assert!(batch.slots.is_empty());
+    }
+
+    #[test]
+    fn test_batch_error() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        assert_eq!(map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Error).unwrap_err(), MultiMutError::Aliased { index: 2 });
+
+        let keys = ["key_one", "key_hundred"];
+        assert_eq!(map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Coalesce).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_batch_panic() {
+        let mut map = populate_hashmap();
+
+        let keys = ["key_one", "key_two", "key_one"];
+        let _ = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Panic);
+    }
+
+    #[test]
+    fn test_multi_iter_size_hint() {
+        let mut map = populate_hashmap();
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three", "key_four"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.len(), 3);
+        wrapper.next();
+        assert_eq!(wrapper.size_hint(), (2, Some(2)));
+    }
+
+    #[test]
+    fn test_multi_iter_rev() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_pair_mut("key_one", "key_hundred"), None);
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three", "key_four"];
+        let values: Vec<_> = map.iter_multi_mut(&keys, &mut buffer).rev().collect();
+
+        assert_eq!(values, vec!["value_three", "value_two", "value_one"]);
     }
 
     #[test]
-    fn test_pair_overlap() {
+    fn test_multi_iter_both_ends() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_pair_mut("key_one", "key_one"), None);
+
+        let mut buffer = [null_mut(); 2];
+        let keys = ["key_one", "key_two", "key_three"];
+        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
+
+        assert_eq!(wrapper.next_back().unwrap(), "value_two");
+        assert_eq!(wrapper.next().unwrap(), "value_one");
+        assert_eq!(wrapper.next(), None);
+        assert_eq!(wrapper.next_back(), None);
     }
 
     #[test]
-    fn test_pair_panic_success() {
+    fn test_multi_iter_collect_array() {
         let mut map = populate_hashmap();
-        let (one, two): (&mut String, &mut String) = map.pair_mut("key_one", "key_two");
-        
+
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two", "key_three"];
+        let [one, two, three] = map.iter_multi_mut(&keys, &mut buffer).collect_array().unwrap();
+
         assert_eq!(one, "value_one");
         assert_eq!(two, "value_two");
+        assert_eq!(three, "value_three");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+        assert_eq!(map.iter_multi_mut(&keys, &mut buffer).collect_array::<3>(), None);
     }
 
+    #[cfg(feature = "rayon")]
     #[test]
-    #[should_panic]
-    fn test_pair_panic_nonexistent_key() {
+    fn test_par_iter_success() {
+        use rayon::iter::ParallelIterator;
+
         let mut map = populate_hashmap();
-        map.pair_mut("key_one", "key_hundred");
+
+        let keys = ["key_one", "key_two", "key_three"];
+        map.par_iter_multi_mut::<str, _>(keys).for_each(|v| v.push_str("_edited"));
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+        assert_eq!(map["key_four"], "value_four");
     }
 
+    #[cfg(feature = "rayon")]
     #[test]
     #[should_panic]
-    fn test_pair_panic_overlap() {
+    fn test_par_iter_same_key() {
         let mut map = populate_hashmap();
-        map.pair_mut("key_one", "key_one");
+
+        let keys = ["key_one", "key_two", "key_one"];
+        map.par_iter_multi_mut::<str, _>(keys);
     }
 
     #[test]
-    fn test_triple_success() {
-        let mut map = populate_hashmap();
-        let (one, two, three): (&mut String, &mut String, &mut String) = map.get_triple_mut("key_one", "key_two", "key_three").unwrap();
-        
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
+    fn test_wrappers_are_send() {
+        fn assert_send<T: Send>(_: &T) {}
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        let mut map = populate_hashmap();
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one"];
+        assert_send(&map.iter_multi_mut(&keys, &mut buffer));
+        assert_send(&map.multi_mut(&mut buffer));
     }
 
     #[test]
-    fn test_triple_nonexistent_key() {
+    fn test_split_for_threads() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_hundred", "key_three"), None);
+
+        {
+            let groups = map.split_for_threads::<str, _, _>(vec![vec!["key_one", "key_two"], vec!["key_three"]]);
+            assert_eq!(groups.len(), 2);
+
+            ::std::thread::scope(|scope| {
+                for group in groups {
+                    scope.spawn(move || {
+                        for value in group {
+                            value.push_str("_edited");
+                        }
+                    });
+                }
+            });
+        }
+
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
+        assert_eq!(map["key_three"], "value_three_edited");
+        assert_eq!(map["key_four"], "value_four");
     }
 
     #[test]
-    fn test_triple_overlap_1() {
+    #[should_panic]
+    fn test_split_for_threads_overlap() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_two", "key_one"), None);
+        map.split_for_threads::<str, _, _>(vec![vec!["key_one", "key_two"], vec!["key_one"]]);
     }
 
-    #[test]
-    fn test_triple_overlap_2() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_two", "key_two", "key_three"), None);
+    fn populate_nested() -> BTreeMap<String, ::std::collections::HashMap<String, Vec<i32>>> {
+        let mut map = BTreeMap::new();
+        let mut tenant_one = ::std::collections::HashMap::new();
+        tenant_one.insert("account_one".to_string(), vec![1, 2]);
+        tenant_one.insert("account_two".to_string(), vec![3]);
+        let mut tenant_two = ::std::collections::HashMap::new();
+        tenant_two.insert("account_one".to_string(), vec![4]);
+        map.insert("tenant_one".to_string(), tenant_one);
+        map.insert("tenant_two".to_string(), tenant_two);
+        map
     }
 
     #[test]
-    fn test_triple_overlap_3() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_three", "key_three"), None);
+    fn test_paths_success() {
+        let mut map = populate_nested();
+
+        {
+            let [from, to, other] = map.get_paths_mut::<str, str, 3>([("tenant_one", "account_one"), ("tenant_two", "account_one"), ("tenant_one", "account_two")]).unwrap();
+            to.append(from);
+            other.push(5);
+        }
+
+        assert_eq!(map["tenant_one"]["account_one"], vec![]);
+        assert_eq!(map["tenant_one"]["account_two"], vec![3, 5]);
+        assert_eq!(map["tenant_two"]["account_one"], vec![4, 1, 2]);
     }
 
     #[test]
-    fn test_triple_overlap_4() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_one", "key_one"), None);
+    fn test_paths_errors() {
+        let mut map = populate_nested();
+
+        assert_eq!(map.get_paths_mut::<str, str, 2>([("tenant_one", "account_one"), ("tenant_one", "account_one")]).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(map.get_paths_mut::<str, str, 2>([("tenant_one", "account_one"), ("tenant_two", "account_two")]).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(map.get_paths_mut::<str, str, 1>([("tenant_three", "account_one")]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
     }
 
     #[test]
-    fn test_triple_panic_success() {
+    fn test_pair_entry_or_insert() {
         let mut map = populate_hashmap();
-        let (one, two, three): (&mut String, &mut String, &mut String) = map.triple_mut("key_one", "key_two", "key_three");
-        
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        {
+            let (one, new) = map.pair_entry_or_insert_with("key_one", "key_new", || "value_new".to_string());
+            assert_eq!(one, "value_one");
+            assert_eq!(new, "value_new");
+            new.push_str("_edited");
+        }
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(map["key_new"], "value_new_edited");
+        assert_eq!(map.len(), 7);
     }
 
     #[test]
     #[should_panic]
-    fn test_triple_panic_nonexistent_key() {
+    fn test_pair_entry_or_insert_overlap() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_hundred", "key_three");
+        map.pair_entry_or_insert_with("key_new", "key_new", String::new);
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_1() {
+    fn test_many_or_insert() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_two", "key_one");
+
+        {
+            let [one, new, other] = map.get_many_or_insert_with(["key_one", "key_new", "key_other"], String::new).unwrap();
+            assert_eq!(one, "value_one");
+            assert_eq!(new, "");
+            assert_eq!(other, "");
+            new.push_str("value_new");
+        }
+
+        assert_eq!(map["key_new"], "value_new");
+        assert_eq!(map.len(), 8);
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_2() {
+    fn test_many_or_insert_overlap() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_two", "key_two", "key_three");
+
+        assert_eq!(map.get_many_or_insert_with(["key_new", "key_one", "key_new"], String::new).unwrap_err(), MultiMutError::Aliased { index: 2 });
+        assert_eq!(map.len(), 6);
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_3() {
+    fn test_pair_or_single() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_three", "key_three");
+
+        match map.get_pair_or_single_mut("key_one", "key_two") {
+            Some(PairOrSingle::Pair(one, two)) => {
+                assert_eq!(one, "value_one");
+                assert_eq!(two, "value_two");
+            },
+            other => panic!("Expected a pair, got {:?}", other),
+        }
+
+        match map.get_pair_or_single_mut("key_one", "key_one") {
+            Some(PairOrSingle::Same(one)) => assert_eq!(one, "value_one"),
+            other => panic!("Expected a single value, got {:?}", other),
+        }
+
+        assert_eq!(map.get_pair_or_single_mut("key_one", "key_hundred"), None);
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_4() {
+    fn test_swap_values() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_one", "key_one");
+
+        map.swap_values("key_one", "key_two").unwrap();
+        assert_eq!(map["key_one"], "value_two");
+        assert_eq!(map["key_two"], "value_one");
+
+        assert_eq!(map.swap_values("key_one", "key_one"), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.swap_values("key_hundred", "key_one"), Err(MultiMutError::NoSuchKey { index: 0 }));
     }
 
     #[test]
-    fn test_multi_success() {
+    fn test_rotate_values() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let one = wrapper.get_mut("key_one").unwrap();
-        let two = wrapper.get_mut("key_two").unwrap();
-        let three = wrapper.get_mut("key_three").unwrap();
-
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
-
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        map.rotate_values(&["key_one", "key_two", "key_three"]).unwrap();
+        assert_eq!(map["key_one"], "value_three");
+        assert_eq!(map["key_two"], "value_one");
+        assert_eq!(map["key_three"], "value_two");
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(map.rotate_values(&["key_one", "key_two", "key_one"]), Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(map["key_one"], "value_three");
     }
 
     #[test]
-    fn test_multi_ref_success() {
+    fn test_permute_values() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let one = wrapper.mut_ref("key_one");
-        let two = wrapper.mut_ref("key_two");
-        let three = wrapper.mut_ref("key_three");
-
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
-
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        map.permute_values(&["key_one", "key_two", "key_three", "key_four"], &[3, 2, 0, 1]).unwrap();
+        assert_eq!(map["key_one"], "value_four");
+        assert_eq!(map["key_two"], "value_three");
+        assert_eq!(map["key_three"], "value_one");
+        assert_eq!(map["key_four"], "value_two");
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(map.permute_values(&["key_one", "key_hundred"], &[1, 0]), Err(MultiMutError::NoSuchKey { index: 1 }));
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_over_capacity() {
+    fn test_permute_values_invalid() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let _one = wrapper.get_mut("key_one").unwrap();
-        let _two = wrapper.get_mut("key_two").unwrap();
-        let _three = wrapper.get_mut("key_three").unwrap();
-        let _four = wrapper.get_mut("key_four").unwrap();
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[1, 1]), Err(MultiMutError::InvalidPermutation { index: 1 }));
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[2, 0]), Err(MultiMutError::InvalidPermutation { index: 0 }));
+        assert_eq!(map.permute_values(&["key_one", "key_two"], &[1]), Err(MultiMutError::InvalidPermutation { index: 1 }));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_same_key() {
+    fn test_merge_into() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let _one = wrapper.get_mut("key_one").unwrap();
-        let _two = wrapper.get_mut("key_two").unwrap();
-        let _three = wrapper.get_mut("key_one").unwrap();
+        let len = map.merge_into("key_two", "key_one", |dst, src| { dst.push_str(&src); dst.len() }).unwrap();
+
+        assert_eq!(len, 18);
+        assert_eq!(map["key_one"], "value_onevalue_two");
+        assert!(!map.contains_key("key_two"));
     }
 
     #[test]
-    fn test_multi_nonexistent() {
+    fn test_merge_into_errors() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        assert_eq!(wrapper.get_mut("key_hundred"), None);
+        assert_eq!(map.merge_into("key_one", "key_one", |_, _| ()), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.merge_into("key_hundred", "key_one", |_, _| ()), Err(MultiMutError::NoSuchKey { index: 0 }));
+        assert_eq!(map.merge_into("key_one", "key_hundred", |_, _| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
+        assert_eq!(map.len(), 6);
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_ref_nonexistent() {
+    fn test_remove_many() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        wrapper.mut_ref("key_hundred");
+        let [(key_1, one), (key_2, two)] = map.remove_many(["key_one", "key_two"]).unwrap();
+
+        assert_eq!((key_1.as_str(), one.as_str()), ("key_one", "value_one"));
+        assert_eq!((key_2.as_str(), two.as_str()), ("key_two", "value_two"));
+        assert_eq!(map.len(), 4);
     }
 
     #[test]
-    fn test_multi_iter_success() {
+    fn test_remove_many_errors() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_three"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let one = wrapper.next().unwrap();
-        let two = wrapper.next().unwrap();
-        let three = wrapper.next().unwrap();
+        assert_eq!(map.remove_many(["key_one", "key_two", "key_one"]).unwrap_err(), MultiMutError::Aliased { index: 2 });
+        assert_eq!(map.remove_many(["key_one", "key_hundred"]).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(map.len(), 6);
+    }
 
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
+    #[test]
+    fn test_transaction_commit() {
+        let mut map = populate_hashmap();
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        let result: Result<usize, TransactionError<()>> = map.transaction(["key_one", "key_two"], |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Ok(one.len())
+        });
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(result, Ok(16));
+        assert_eq!(map["key_one"], "value_one_edited");
+        assert_eq!(map["key_two"], "value_two_edited");
     }
 
     #[test]
-    fn test_multi_iter_over_capacity() {
+    fn test_transaction_rollback() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_three"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let _one = wrapper.next().unwrap();
-        let _two = wrapper.next().unwrap();
-        let _three = wrapper.next().unwrap();
+        let result: Result<(), _> = map.transaction(["key_one", "key_two"], |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Err("insufficient funds")
+        });
 
-        assert_eq!(wrapper.next(), None);
+        assert_eq!(result, Err(TransactionError::Aborted("insufficient funds")));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_iter_same_key() {
+    fn test_transaction_rollback_on_panic() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_one"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let _one = wrapper.next().unwrap();
-        let _two = wrapper.next().unwrap();
-        let _three = wrapper.next().unwrap();
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            let _: Result<(), TransactionError<()>> = map.transaction(["key_one", "key_two"], |[one, _two]| {
+                one.push_str("_edited");
+                panic!("Something went wrong!");
+            });
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(map["key_one"], "value_one");
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_iter_nonexistent() {
+    fn test_transaction_with_undo() {
         let mut map = populate_hashmap();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_hundred"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        wrapper.next();
+        let result: Result<(), _> = map.transaction_with(["key_one", "key_two"], String::len, |v, len| v.truncate(len), |[one, two]| {
+            one.push_str("_edited");
+            two.push_str("_edited");
+            Err(())
+        });
+
+        assert_eq!(result, Err(TransactionError::Aborted(())));
+        assert_eq!(map["key_one"], "value_one");
+        assert_eq!(map["key_two"], "value_two");
+
+        let result: Result<(), TransactionError<()>> = map.transaction(["key_one", "key_one"], |_| Ok(()));
+        assert_eq!(result, Err(TransactionError::Keys(MultiMutError::Aliased { index: 1 })));
     }
 
-}
+    #[test]
+    fn test_with_pair_and_triple() {
+        let mut map = populate_hashmap();
 
+        let len = map.with_pair_mut("key_one", "key_two", |one, two| { one.push_str(two); one.len() }).unwrap();
+        assert_eq!(len, 18);
+        assert_eq!(map["key_one"], "value_onevalue_two");
 
+        map.with_triple_mut("key_one", "key_two", "key_three", |one, two, three| {
+            ::std::mem::swap(one, two);
+            three.clear();
+        }).unwrap();
+        assert_eq!(map["key_one"], "value_two");
+        assert_eq!(map["key_three"], "");
 
+        assert_eq!(map.with_pair_mut("key_one", "key_one", |_, _| ()), Err(MultiMutError::Aliased { index: 1 }));
+        assert_eq!(map.with_triple_mut("key_one", "key_two", "key_hundred", |_, _, _| ()), Err(MultiMutError::NoSuchKey { index: 2 }));
+    }
 
-#[cfg(test)]
-mod tests_btree {
+    #[test]
+    fn test_with_many() {
+        let mut map = populate_hashmap();
 
-    use std::collections::BTreeMap;
-    use BTreeMapMultiMut;
-    use std::ptr::null_mut;
+        let total = map.with_many_mut(["key_one", "key_two", "key_three", "key_four"], |values| {
+            values.iter().map(|v| v.len()).sum::<usize>()
+        }).unwrap();
+        assert_eq!(total, 9 + 9 + 11 + 10);
 
-    fn populate_hashmap() -> BTreeMap<String, String> {
-        let mut map = BTreeMap::new();
-        map.insert("key_one".into(), "value_one".into());
-        map.insert("key_two".into(), "value_two".into());
-        map.insert("key_three".into(), "value_three".into());
-        map.insert("key_four".into(), "value_four".into());
-        map.insert("key_five".into(), "value_five".into());
-        map.insert("key_six".into(), "value_six".into());
-        map
+        assert_eq!(map.with_many_mut(["key_one", "key_two", "key_one"], |_| ()), Err(MultiMutError::Aliased { index: 2 }));
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let mut tree: BTreeMap<u32, (Option<u32>, u32)> = BTreeMap::new();
+        tree.insert(1, (None, 0));
+        tree.insert(2, (Some(1), 0));
+        tree.insert(3, (Some(2), 0));
+        tree.insert(4, (Some(1), 0));
+
+        {
+            let chain = tree.follow_chain_mut(&3, |node| node.0).unwrap();
+            assert_eq!(chain.len(), 3);
+            for node in chain {
+                node.1 += 1;
+            }
+        }
+
+        assert_eq!(tree[&1].1, 1);
+        assert_eq!(tree[&2].1, 1);
+        assert_eq!(tree[&3].1, 1);
+        assert_eq!(tree[&4].1, 0);
     }
 
     #[test]
-    fn test_pair_success() {
-        let mut map = populate_hashmap();
-        let (one, two): (&mut String, &mut String) = map.get_pair_mut("key_one", "key_two").unwrap();
-        
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-
-        one.push_str("_edited");
-        two.push_str("_edited");
-
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
+    fn test_follow_chain_errors() {
+        let mut tree: BTreeMap<u32, Option<u32>> = BTreeMap::new();
+        tree.insert(1, Some(3));
+        tree.insert(2, Some(1));
+        tree.insert(3, Some(2));
+        tree.insert(4, Some(5));
+        tree.insert(6, Some(6));
+
+        assert_eq!(tree.follow_chain_mut(&1, |link| *link).unwrap_err(), MultiMutError::Aliased { index: 3 });
+        assert_eq!(tree.follow_chain_mut(&4, |link| *link).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(tree.follow_chain_mut(&6, |link| *link).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(tree.follow_chain_mut(&7, |link| *link).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
     }
 
     #[test]
-    fn test_pair_nonexistent_key() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_pair_mut("key_one", "key_hundred"), None);
+    fn test_with_neighbors() {
+        let mut grid: BTreeMap<(i32, i32), u32> = BTreeMap::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                grid.insert((x, y), 1);
+            }
+        }
+
+        {
+            let (x, y) = (0, 1);
+            let (center, neighbors) = grid.get_with_neighbors_mut(&(x, y), vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]).unwrap();
+            assert_eq!(neighbors.len(), 4);
+            assert!(neighbors[0].is_none());
+            for neighbor in neighbors.into_iter().flatten() {
+                *center += *neighbor;
+                *neighbor = 0;
+            }
+        }
+
+        assert_eq!(grid[&(0, 1)], 4);
+        assert_eq!(grid[&(1, 1)], 0);
+        assert_eq!(grid[&(0, 0)], 0);
+        assert_eq!(grid[&(1, 0)], 1);
     }
 
     #[test]
-    fn test_pair_overlap() {
+    fn test_with_neighbors_overlap() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_pair_mut("key_one", "key_one"), None);
+
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_hundred"]).is_some());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_one"]).is_none());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_one", ["key_two", "key_two"]).is_none());
+        assert!(map.get_with_neighbors_mut::<str, _>("key_hundred", ["key_two"]).is_none());
     }
 
     #[test]
-    fn test_pair_panic_success() {
+    fn test_many_opt() {
         let mut map = populate_hashmap();
-        let (one, two): (&mut String, &mut String) = map.pair_mut("key_one", "key_two");
-        
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
+        let [one, missing, three] = map.get_many_mut_opt(["key_one", "key_hundred", "key_three"]).unwrap();
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
+        assert_eq!(one.unwrap(), "value_one");
+        assert_eq!(missing, None);
+        assert_eq!(three.unwrap(), "value_three");
     }
 
     #[test]
-    #[should_panic]
-    fn test_pair_panic_nonexistent_key() {
+    fn test_many_opt_overlap() {
         let mut map = populate_hashmap();
-        map.pair_mut("key_one", "key_hundred");
+
+        assert_eq!(map.get_many_mut_opt(["key_hundred", "key_hundred", "key_one"]).map(|_| ()), Ok(()));
+        assert_eq!(map.get_many_mut_opt(["key_one", "key_hundred", "key_one"]).unwrap_err(), AliasError { first: 0, index: 2 });
+        assert_eq!(MultiMutError::from(AliasError { first: 0, index: 2 }), MultiMutError::Aliased { index: 2 });
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    #[should_panic]
-    fn test_pair_panic_overlap() {
+    fn test_many_vec() {
         let mut map = populate_hashmap();
-        map.pair_mut("key_one", "key_one");
+
+        let keys: Vec<&str> = "key_one key_two key_three".split(' ').collect();
+        let values = map.get_many_mut_vec(&keys).unwrap();
+        assert_eq!(values, vec!["value_one", "value_two", "value_three"]);
+
+        assert_eq!(map.get_many_mut_vec(&["key_one", "key_one"]).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(map.get_many_mut_vec(&["key_hundred"]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
     }
 
     #[test]
-    fn test_triple_success() {
+    fn test_tuple() {
         let mut map = populate_hashmap();
-        let (one, two, three): (&mut String, &mut String, &mut String) = map.get_triple_mut("key_one", "key_two", "key_three").unwrap();
-        
+
+        let two = String::from("key_two");
+        let (one, two, three) = map.get_tuple_mut(("key_one", &two, "key_three")).unwrap();
         assert_eq!(one, "value_one");
         assert_eq!(two, "value_two");
         assert_eq!(three, "value_three");
+        ::std::mem::swap(one, three);
+        assert_eq!(map["key_one"], "value_three");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
-
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(map.get_tuple_mut(("key_one", "key_two", &String::from("key_one"))).map(|_| ()), Err(MultiMutError::Aliased { index: 2 }));
+        assert_eq!(map.get_tuple_mut(("key_one", "key_hundred")).map(|_| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
     }
 
     #[test]
-    fn test_triple_nonexistent_key() {
+    fn test_macro() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_hundred", "key_three"), None);
+
+        let two = String::from("key_two");
+        let (one, two, three) = multi_mut!(map, "key_one", &two, "key_three");
+        assert_eq!(one, "value_one");
+        assert_eq!(two, "value_two");
+        ::std::mem::swap(one, three);
+        assert_eq!(map["key_three"], "value_one");
+
+        let (four,) = multi_mut!(map, "key_four",);
+        assert_eq!(four, "value_four");
     }
 
     #[test]
-    fn test_triple_overlap_1() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_two", "key_one"), None);
+    fn test_macro_integer_literals() {
+        let mut map: BTreeMap<u64, i32> = (0..4).map(|i| (i, 0)).collect();
+
+        let (a, b, c) = multi_mut!(map, 1, 0x2, 3);
+        *a += 1;
+        *b += 2;
+        *c += 3;
+        assert_eq!(map[&1] + map[&2] + map[&3], 6);
+
+        let mut map: BTreeMap<Vec<u8>, i32> = vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2)].into_iter().collect();
+        let (a, b) = multi_mut!(map, b"a", b"b");
+        ::std::mem::swap(a, b);
+        assert_eq!(map[&b"a"[..]], 2);
     }
 
     #[test]
-    fn test_triple_overlap_2() {
-        let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_two", "key_two", "key_three"), None);
+    fn test_macro_arity() {
+        let mut map: BTreeMap<i32, i32> = (0..20).map(|i| (i, i)).collect();
+
+        let keys: Vec<i32> = (0..13).collect();
+        let (a, _, _, _, _, _, _, _, _, _, _, _, m) = multi_mut!(map, &keys[0], &keys[1], &keys[2], &keys[3], &keys[4],
+            &keys[5], &keys[6], &keys[7], &keys[8], &keys[9], &keys[10], &keys[11], &keys[12]);
+        *a += 100;
+        *m += 100;
+        assert_eq!(map[&0], 100);
+        assert_eq!(map[&12], 112);
     }
 
     #[test]
-    fn test_triple_overlap_3() {
+    #[should_panic]
+    fn test_macro_overlap() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_three", "key_three"), None);
+
+        let one = String::from("key_one");
+        let (_, _) = multi_mut!(map, "key_one", &one);
     }
 
     #[test]
-    fn test_triple_overlap_4() {
+    #[should_panic]
+    fn test_macro_no_such_key() {
         let mut map = populate_hashmap();
-        assert_eq!(map.get_triple_mut("key_one", "key_one", "key_one"), None);
+
+        let (_, _) = multi_mut!(map, "key_one", "key_hundred");
     }
 
+    #[cfg(feature = "derive")]
     #[test]
-    fn test_triple_panic_success() {
+    fn test_named() {
+        use MultiMutKeys;
+
+        #[derive(MultiMutKeys)]
+        struct Transfer<'k> {
+            src: &'k str,
+            dst: String,
+            fee: &'k str,
+        }
+
         let mut map = populate_hashmap();
-        let (one, two, three): (&mut String, &mut String, &mut String) = map.triple_mut("key_one", "key_two", "key_three");
-        
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        let keys = Transfer { src: "key_one", dst: String::from("key_two"), fee: "key_three" };
+        let TransferMut { src, dst, fee } = map.get_named_mut(&keys).unwrap();
+        assert_eq!(fee, "value_three");
+        ::std::mem::swap(src, dst);
+        assert_eq!(map["key_one"], "value_two");
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        let keys = Transfer { src: "key_one", dst: String::from("key_hundred"), fee: "key_three" };
+        assert_eq!(map.get_named_mut(&keys).map(|_| ()), Err(MultiMutError::NoSuchKey { index: 1 }));
+        let keys = Transfer { src: "key_one", dst: String::from("key_two"), fee: "key_one" };
+        assert_eq!(map.get_named_mut(&keys).map(|_| ()), Err(MultiMutError::Aliased { index: 2 }));
     }
 
+    #[cfg(feature = "derive")]
     #[test]
-    #[should_panic]
-    fn test_triple_panic_nonexistent_key() {
+    fn test_named_generic_defaults() {
+        use MultiMutKeys;
+
+        #[derive(MultiMutKeys)]
+        struct Pair<T = String, const N: usize = 2> {
+            first: T,
+            second: T,
+        }
+
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_hundred", "key_three");
+
+        let keys: Pair = Pair { first: "key_one".into(), second: "key_two".into() };
+        let PairMut { first, second } = map.get_named_mut(&keys).unwrap();
+        ::std::mem::swap(first, second);
+        assert_eq!(map["key_one"], "value_two");
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_1() {
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_macro_panic_names_key() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_two", "key_one");
+
+        let (_, _, _) = multi_mut!(map, "key_one", "key_hundred", "key_two");
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_2() {
+    #[should_panic(expected = "The key at index 2 has been already used. The key was \"key_one\".")]
+    fn test_macro_panic_names_aliased_key() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_two", "key_two", "key_three");
+
+        let one = String::from("key_one");
+        let (_, _, _) = multi_mut!(map, "key_one", "key_two", &one);
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_3() {
+    #[should_panic(expected = "No such key! (key at index 0)")]
+    fn test_macro_panic_without_debug() {
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct Opaque(u32);
+
+        let mut map = BTreeMap::new();
+        map.insert(Opaque(1), 1);
+        let (_, _) = multi_mut!(map, &Opaque(2), &Opaque(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 2)")]
+    fn test_mut_ref_panic_index() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_three", "key_three");
+        let mut buffer = [null_mut(); 3];
+        let mut wrapper = map.multi_mut(&mut buffer);
+
+        wrapper.mut_ref("key_one");
+        wrapper.mut_ref("key_two");
+        wrapper.mut_ref("key_hundred");
     }
 
     #[test]
-    #[should_panic]
-    fn test_triple_panic_overlap_4() {
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_pair_debug_panic_names_key() {
         let mut map = populate_hashmap();
-        map.triple_mut("key_one", "key_one", "key_one");
+        map.pair_mut_debug("key_one", "key_hundred");
     }
 
     #[test]
-    fn test_multi_success() {
+    #[should_panic(expected = "The keys at indices 0 and 2 point to the same value. The key was \"key_one\".")]
+    fn test_triple_debug_panic_names_key() {
         let mut map = populate_hashmap();
+        map.triple_mut_debug("key_one", "key_two", "key_one");
+    }
 
+    #[test]
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_one\".")]
+    fn test_mut_ref_debug_panic_names_key() {
+        let mut map = populate_hashmap();
         let mut buffer = [null_mut(); 3];
         let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let one = wrapper.get_mut("key_one").unwrap();
-        let two = wrapper.get_mut("key_two").unwrap();
-        let three = wrapper.get_mut("key_three").unwrap();
-
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+        wrapper.mut_ref_debug("key_one");
+        wrapper.mut_ref_debug("key_one");
+    }
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+    /// Runs `f`, which is expected to panic, and returns the file and line the panic was reported at,
+    /// along with the payload to resume the panic with.
+    fn panic_location<F: FnOnce()>(f: F) -> ((String, u32), Box<dyn Any + Send>) {
+        thread_local!(static LOCATION: RefCell<Option<(String, u32)>> = const { RefCell::new(None) });
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if let Some(location) = info.location() {
+                    LOCATION.with(|l| *l.borrow_mut() = Some((location.file().to_string(), location.line())));
+                }
+                default_hook(info);
+            }));
+        });
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_err();
+        (LOCATION.with(|l| l.borrow_mut().take()).unwrap(), payload)
     }
 
     #[test]
-    fn test_multi_ref_success() {
+    #[should_panic(expected = "No aliased references allowed! The keys at indices 0 and 2 point to the same value.")]
+    fn test_batch_panic_location() {
         let mut map = populate_hashmap();
 
+        let keys = ["key_one", "key_two", "key_one"];
+        let (location, payload) = panic_location(|| { let _ = map.multi_mut_batch::<str, _>(keys, DuplicatePolicy::Panic); });
+        assert_eq!(location, (file!().to_string(), line!() - 1));
+        panic::resume_unwind(payload);
+    }
+
+    #[test]
+    #[should_panic(expected = "No such key! (key at index 1) The key was \"key_hundred\".")]
+    fn test_iter_debug_keys_panic_names_key() {
+        let mut map = populate_hashmap();
         let mut buffer = [null_mut(); 3];
-        let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let one = wrapper.mut_ref("key_one");
-        let two = wrapper.mut_ref("key_two");
-        let three = wrapper.mut_ref("key_three");
+        let keys = ["key_one", "key_hundred"];
 
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
+        for _ in map.iter_multi_mut(&keys, &mut buffer).debug_keys() {}
+    }
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+    #[test]
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_two\".")]
+    fn test_entries_debug_keys_panic_names_key() {
+        let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        for _ in map.iter_multi_mut_entries::<str, _>(["key_two", "key_two"], &mut buffer).debug_keys() {}
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_over_capacity() {
+    #[should_panic(expected = "The key at index 1 has been already used. The key was \"key_one\".")]
+    fn test_get_key_value_mut_debug_panic_names_key() {
         let mut map = populate_hashmap();
-
         let mut buffer = [null_mut(); 3];
         let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let _one = wrapper.get_mut("key_one").unwrap();
-        let _two = wrapper.get_mut("key_two").unwrap();
-        let _three = wrapper.get_mut("key_three").unwrap();
-        let _four = wrapper.get_mut("key_four").unwrap();
+
+        assert_eq!(wrapper.get_key_value_mut_debug("key_hundred"), None);
+        wrapper.get_key_value_mut_debug("key_one").unwrap();
+        wrapper.get_key_value_mut_debug("key_one");
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_same_key() {
+    fn test_wrapper_debug() {
         let mut map = populate_hashmap();
-
         let mut buffer = [null_mut(); 3];
         let mut wrapper = map.multi_mut(&mut buffer);
-        
-        let _one = wrapper.get_mut("key_one").unwrap();
-        let _two = wrapper.get_mut("key_two").unwrap();
-        let _three = wrapper.get_mut("key_one").unwrap();
+
+        wrapper.get_mut("key_one").unwrap();
+        wrapper.get_mut_debug("key_two").unwrap();
+        assert!(format!("{:?}", wrapper).starts_with("BTreeMapMutWrapper { used: 2, capacity: 3"));
     }
 
+    #[cfg(feature = "audit")]
     #[test]
-    fn test_multi_nonexistent() {
+    fn test_audit() {
         let mut map = populate_hashmap();
-
         let mut buffer = [null_mut(); 3];
         let mut wrapper = map.multi_mut(&mut buffer);
-        
-        assert_eq!(wrapper.get_mut("key_hundred"), None);
+
+        let line = line!() + 1;
+        wrapper.get_mut_debug("key_one").unwrap();
+        wrapper.get_mut("key_two").unwrap();
+        assert_eq!(wrapper.get_mut_debug("key_hundred"), None);
+
+        let sites = wrapper.borrow_sites().to_vec();
+        assert_eq!(sites.len(), 2);
+        assert_eq!((sites[0].location.file(), sites[0].location.line()), (file!(), line));
+        assert_eq!(sites[1].location.line(), line + 1);
+        assert_eq!(sites[0].key, Some("\"key_one\"".to_string()));
+        assert_eq!(sites[1].key, None);
+        let first = format!("\"key_one\" at {}:{}:", file!(), line);
+        assert!(format!("{:?}", wrapper).contains(&format!("borrows: [{}", first)));
+
+        let payload = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| { wrapper.mut_ref("key_one"); })).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(message.contains(&format!("First borrowed: {}", first)), "{}", message);
+        assert!(message.contains(&format!("and again at {}:{}:", file!(), line!() - 3)), "{}", message);
     }
 
+    #[cfg(feature = "audit")]
     #[test]
-    #[should_panic]
-    fn test_multi_ref_nonexistent() {
+    fn test_audit_iter_keys() {
         let mut map = populate_hashmap();
+        let mut buffer = [null_mut(); 3];
+        let keys = ["key_one", "key_two"];
+
+        // Calling next() directly records the line of the call, unlike a for loop, which records a line inside std.
+        let mut iter = map.iter_multi_mut(&keys, &mut buffer).debug_keys();
+        let line = line!() + 1;
+        iter.next().unwrap();
+        iter.next().unwrap();
+        let sites = iter.mut_wrapper.borrow_sites().to_vec();
+        assert_eq!(sites.iter().map(|site| site.key.clone()).collect::<Vec<_>>(),
+                   vec![Some("\"key_one\"".to_string()), Some("\"key_two\"".to_string())]);
+        assert_eq!((sites[0].location.file(), sites[0].location.line()), (file!(), line));
+        assert_eq!(sites[1].location.line(), line + 1);
 
         let mut buffer = [null_mut(); 3];
         let mut wrapper = map.multi_mut(&mut buffer);
-        
-        wrapper.mut_ref("key_hundred");
+        wrapper.get_key_value_mut_debug("key_three").unwrap();
+        wrapper.mut_ref_debug("key_one");
+        let sites = wrapper.borrow_sites();
+        assert_eq!(sites[0].key, Some("\"key_three\"".to_string()));
+        assert_eq!(sites[1].key, Some("\"key_one\"".to_string()));
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests_grid {
+
+    use {GridMultiMut, FlatGrid, MultiMutError};
+
+    fn populate_grid() -> Vec<Vec<u32>> {
+        vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8]]
     }
 
     #[test]
-    fn test_multi_iter_success() {
-        let mut map = populate_hashmap();
+    fn test_cells_success() {
+        let mut grid = populate_grid();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_three"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let one = wrapper.next().unwrap();
-        let two = wrapper.next().unwrap();
-        let three = wrapper.next().unwrap();
+        {
+            let [a, b] = grid.get_cells_mut([(0, 0), (2, 1)]).unwrap();
+            ::std::mem::swap(a, b);
+        }
 
-        assert_eq!(one, "value_one");
-        assert_eq!(two, "value_two");
-        assert_eq!(three, "value_three");
+        assert_eq!(grid, vec![vec![7, 1, 2], vec![3, 4, 5], vec![6, 0, 8]]);
+    }
 
-        one.push_str("_edited");
-        two.push_str("_edited");
-        three.push_str("_edited");
+    #[test]
+    fn test_cells_errors() {
+        let mut grid = populate_grid();
 
-        assert_eq!(one, "value_one_edited");
-        assert_eq!(two, "value_two_edited");
-        assert_eq!(three, "value_three_edited");
+        assert_eq!(grid.get_cells_mut([(0, 0), (0, 0)]).unwrap_err(), MultiMutError::Aliased { index: 1 });
+        assert_eq!(grid.get_cells_mut([(0, 0), (0, 3)]).unwrap_err(), MultiMutError::NoSuchKey { index: 1 });
+        assert_eq!(grid.get_cells_mut([(3, 0)]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
     }
 
     #[test]
-    fn test_multi_iter_over_capacity() {
-        let mut map = populate_hashmap();
+    fn test_rows() {
+        let mut grid = populate_grid();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_three"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let _one = wrapper.next().unwrap();
-        let _two = wrapper.next().unwrap();
-        let _three = wrapper.next().unwrap();
+        {
+            let [top, bottom] = grid.get_rows_mut([0, 2]).unwrap();
+            top.swap_with_slice(bottom);
+        }
 
-        assert_eq!(wrapper.next(), None);
+        assert_eq!(grid, vec![vec![6, 7, 8], vec![3, 4, 5], vec![0, 1, 2]]);
+        assert_eq!(grid.get_rows_mut([1, 1]).unwrap_err(), MultiMutError::Aliased { index: 1 });
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_iter_same_key() {
-        let mut map = populate_hashmap();
+    fn test_neighbors() {
+        let mut grid = populate_grid();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_one", "key_two", "key_one"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        let _one = wrapper.next().unwrap();
-        let _two = wrapper.next().unwrap();
-        let _three = wrapper.next().unwrap();
+        {
+            let (center, neighbors) = grid.get_with_neighbors_mut(0, 1).unwrap();
+            assert_eq!(neighbors.iter().filter(|n| n.is_none()).count(), 3);
+            *center = neighbors.iter().flatten().map(|n| **n).sum();
+        }
+
+        assert_eq!(grid[0][1], 2 + 3 + 4 + 5);
+        assert!(grid.get_with_neighbors_mut(3, 0).is_none());
     }
 
     #[test]
-    #[should_panic]
-    fn test_multi_iter_nonexistent() {
-        let mut map = populate_hashmap();
+    fn test_flat_grid() {
+        let mut cells: Vec<u32> = (0..10).collect();
 
-        let mut buffer = [null_mut(); 3];
-        let keys = ["key_hundred"];
-        let mut wrapper = map.iter_multi_mut(&keys, &mut buffer);
-        
-        wrapper.next();
+        {
+            let mut grid = FlatGrid::new(&mut cells, 3);
+            assert_eq!(grid.height(), 3);
+
+            let [a, b] = grid.get_cells_mut([(0, 0), (2, 2)]).unwrap();
+            ::std::mem::swap(a, b);
+
+            let [row] = grid.get_rows_mut([1]).unwrap();
+            assert_eq!(row, &[3, 4, 5]);
+
+            assert_eq!(grid.get_cells_mut([(3, 0)]).unwrap_err(), MultiMutError::NoSuchKey { index: 0 });
+
+            let (center, neighbors) = grid.get_with_neighbors_mut(1, 1).unwrap();
+            assert_eq!(*center, 4);
+            assert_eq!(neighbors.iter().flatten().count(), 8);
+        }
+
+        assert_eq!(cells, vec![8, 1, 2, 3, 4, 5, 6, 7, 0, 9]);
     }
 
 }